@@ -9,21 +9,32 @@
 
 use platform::{self, OsIpcChannel, OsIpcReceiver, OsIpcReceiverSet, OsIpcSender};
 use platform::{OsIpcOneShotServer, OsIpcSelectionResult, OsIpcSharedMemory, OsOpaqueIpcChannel};
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+use platform::OsIpcServer;
 
 use bincode;
+#[cfg(unix)]
+use libc;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::cell::RefCell;
+use serde::{de, ser};
+use std::cell::{Cell, RefCell};
 use std::cmp::min;
+use std::collections::HashMap;
+use std::error;
 use std::fmt::{self, Debug, Formatter};
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "async")]
 use futures::{Async, Poll, Stream};
-#[cfg(feature = "async")]
-use std::io::ErrorKind;
 
 thread_local! {
     static OS_IPC_CHANNELS_FOR_DESERIALIZATION: RefCell<Vec<OsOpaqueIpcChannel>> =
@@ -66,6 +77,72 @@ pub fn bytes_channel() -> Result<(IpcBytesSender, IpcBytesReceiver),Error> {
     Ok((ipc_bytes_sender, ipc_bytes_receiver))
 }
 
+/// Creates a bounded channel, like `std::sync::mpsc::sync_channel()`.
+///
+/// At most `capacity` messages the receiver hasn't taken yet may be in flight, across all
+/// senders of the channel, including clones and senders transferred to another process;
+/// `send()` blocks beyond that. With a `capacity` of 0, `send()` blocks until the receiver
+/// has taken a message.
+///
+/// Each free slot is a message queued on a channel of its own, so the `capacity` is limited
+/// by how many messages a channel can buffer. That depends on the platform and its settings;
+/// with Unix sockets at the default buffer size, it's a few hundred. Larger capacities are
+/// rejected with `ErrorKind::InvalidInput`.
+pub fn sync_channel<T>(capacity: usize) -> Result<(IpcSyncSender<T>, IpcSyncReceiver<T>),Error>
+                       where T: for<'de> Deserialize<'de> + Serialize {
+    let (sender, receiver) = try!(channel());
+    let (credit_sender, credit_receiver) = try!(channel());
+    let ipc_sync_receiver = IpcSyncReceiver {
+        receiver: receiver,
+        credit_sender: credit_sender,
+        pending_credits: Cell::new(capacity),
+    };
+    ipc_sync_receiver.return_credits();
+    if ipc_sync_receiver.pending_credits.get() > 0 {
+        return Err(Error::new(ErrorKind::InvalidInput,
+                              "capacity exceeds what the credit channel can hold"))
+    }
+    let ipc_sync_sender = IpcSyncSender::new(sender, capacity, credit_receiver);
+    Ok((ipc_sync_sender, ipc_sync_receiver))
+}
+
+/// Spawns `command` with a channel to it and one back. The child process picks up its ends
+/// with `bootstrap()`; they are inherited, so there's no name anyone else could connect to.
+///
+/// Only available with Unix sockets, so not on macOS.
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+pub fn spawn<T,U>(command: &mut Command) -> Result<(Child, IpcSender<T>, IpcReceiver<U>),Error>
+                  where T: Serialize, U: for<'de> Deserialize<'de> + Serialize {
+    let (child, os_sender, os_receiver) = try!(platform::spawn(command));
+    let ipc_sender = IpcSender {
+        os_sender: os_sender,
+        phantom: PhantomData,
+    };
+    let ipc_receiver = IpcReceiver {
+        os_receiver: os_receiver,
+        phantom: PhantomData,
+    };
+    Ok((child, ipc_sender, ipc_receiver))
+}
+
+/// Takes the channels from and to the parent, in a process started with `spawn()`.
+///
+/// Fails if the process wasn't started that way, or if they have been taken already.
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+pub fn bootstrap<T,U>() -> Result<(IpcReceiver<T>, IpcSender<U>),Error>
+                      where T: for<'de> Deserialize<'de> + Serialize, U: Serialize {
+    let (os_receiver, os_sender) = try!(platform::bootstrap());
+    let ipc_receiver = IpcReceiver {
+        os_receiver: os_receiver,
+        phantom: PhantomData,
+    };
+    let ipc_sender = IpcSender {
+        os_sender: os_sender,
+        phantom: PhantomData,
+    };
+    Ok((ipc_receiver, ipc_sender))
+}
+
 #[derive(Debug)]
 pub struct IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
     os_receiver: OsIpcReceiver,
@@ -73,15 +150,68 @@ pub struct IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
 }
 
 impl<T> IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
-    pub fn recv(&self) -> Result<T, bincode::Error> {
+    pub fn recv(&self) -> Result<T, RecvError> {
         let (data, os_ipc_channels, os_ipc_shared_memory_regions) = try!(self.os_receiver.recv());
-        OpaqueIpcMessage::new(data, os_ipc_channels, os_ipc_shared_memory_regions).to()
+        OpaqueIpcMessage::new(data, os_ipc_channels, os_ipc_shared_memory_regions)
+            .to()
+            .map_err(RecvError::Deserialize)
     }
 
-    pub fn try_recv(&self) -> Result<T, bincode::Error> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
         let (data, os_ipc_channels, os_ipc_shared_memory_regions) =
             try!(self.os_receiver.try_recv());
-        OpaqueIpcMessage::new(data, os_ipc_channels, os_ipc_shared_memory_regions).to()
+        OpaqueIpcMessage::new(data, os_ipc_channels, os_ipc_shared_memory_regions)
+            .to()
+            .map_err(TryRecvError::Deserialize)
+    }
+
+    /// Blocks for at most `duration` waiting for a message.
+    pub fn recv_timeout(&self, duration: Duration) -> Result<T, RecvTimeoutError> {
+        let (data, os_ipc_channels, os_ipc_shared_memory_regions) =
+            try!(self.os_receiver.recv_timeout(duration));
+        OpaqueIpcMessage::new(data, os_ipc_channels, os_ipc_shared_memory_regions)
+            .to()
+            .map_err(RecvTimeoutError::Deserialize)
+    }
+
+    /// Like `recv_timeout()`, but waits until the given point in time.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let (data, os_ipc_channels, os_ipc_shared_memory_regions) =
+            try!(self.os_receiver.recv_deadline(deadline));
+        OpaqueIpcMessage::new(data, os_ipc_channels, os_ipc_shared_memory_regions)
+            .to()
+            .map_err(RecvTimeoutError::Deserialize)
+    }
+
+    /// Returns an iterator that blocks waiting for messages,
+    /// ending once all senders have been dropped.
+    pub fn iter(&self) -> Iter<T> {
+        Iter {
+            receiver: self,
+        }
+    }
+
+    /// Returns an iterator over the messages currently pending, without blocking.
+    pub fn try_iter(&self) -> TryIter<T> {
+        TryIter {
+            receiver: self,
+        }
+    }
+
+    /// The credentials of the process at the other end of the connection.
+    ///
+    /// On Unix, for receivers from `channel()` rather than from a server,
+    /// that's the process which created the channel. Not supported on macOS.
+    pub fn peer_credentials(&self) -> Result<PeerCredentials,Error> {
+        Ok(try!(self.os_receiver.peer_credentials()))
+    }
+
+    /// Another receiver taking messages from the same channel, where the platform allows it.
+    fn try_clone(&self) -> Result<IpcReceiver<T>,Error> {
+        Ok(IpcReceiver {
+            os_receiver: try!(self.os_receiver.try_clone()),
+            phantom: PhantomData,
+        })
     }
 
     pub fn to_opaque(self) -> OpaqueIpcReceiver {
@@ -94,33 +224,22 @@ impl<T> IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
 #[cfg(feature = "async")]
 impl<T> Stream for IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
     type Item = T;
-    type Error = bincode::Error;
+    type Error = RecvError;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         match self.try_recv() {
             Ok(msg) => Ok(Some(msg).into()),
-            Err(err) => match *err {
-                bincode::ErrorKind::IoError(ref e) if e.kind() == ErrorKind::ConnectionReset => {
-                    Ok(Async::Ready(None))
-                }
-                bincode::ErrorKind::IoError(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    Ok(Async::NotReady)
-                }
-                _ => Err(err),
-            },
+            Err(TryRecvError::Disconnected) => Ok(Async::Ready(None)),
+            Err(TryRecvError::Empty) => Ok(Async::NotReady),
+            Err(TryRecvError::Io(err)) => Err(RecvError::Io(err)),
+            Err(TryRecvError::Deserialize(err)) => Err(RecvError::Deserialize(err)),
         }
     }
 }
 
 impl<'de, T> Deserialize<'de> for IpcReceiver<T> where T: for<'dde> Deserialize<'dde> + Serialize {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
-        let index: usize = try!(Deserialize::deserialize(deserializer));
-        let os_receiver =
-            OS_IPC_CHANNELS_FOR_DESERIALIZATION.with(|os_ipc_channels_for_deserialization| {
-                // FIXME(pcwalton): This could panic if the data was corrupt and the index was out
-                // of bounds. We should return an `Err` result instead.
-                os_ipc_channels_for_deserialization.borrow_mut()[index].to_receiver()
-            });
+        let os_receiver = try!(deserialize_os_ipc_receiver(deserializer));
         Ok(IpcReceiver {
             os_receiver: os_receiver,
             phantom: PhantomData,
@@ -142,6 +261,88 @@ impl<T> Serialize for IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serial
     }
 }
 
+/// An iterator over the messages of an `IpcReceiver`, created by `IpcReceiver::iter()`.
+///
+/// Like `std::sync::mpsc::Iter`, this ends once the channel is disconnected;
+/// any other errors are yielded as items.
+#[derive(Debug)]
+pub struct Iter<'a, T: 'a> where T: for<'de> Deserialize<'de> + Serialize {
+    receiver: &'a IpcReceiver<T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> where T: for<'de> Deserialize<'de> + Serialize {
+    type Item = Result<T, RecvError>;
+
+    fn next(&mut self) -> Option<Result<T, RecvError>> {
+        match self.receiver.recv() {
+            Err(RecvError::Disconnected) => None,
+            result => Some(result),
+        }
+    }
+}
+
+/// An iterator over the pending messages of an `IpcReceiver`,
+/// created by `IpcReceiver::try_iter()`.
+///
+/// This ends once no more messages are pending, or the channel is disconnected;
+/// any other errors are yielded as items.
+#[derive(Debug)]
+pub struct TryIter<'a, T: 'a> where T: for<'de> Deserialize<'de> + Serialize {
+    receiver: &'a IpcReceiver<T>,
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> where T: for<'de> Deserialize<'de> + Serialize {
+    type Item = Result<T, RecvError>;
+
+    fn next(&mut self) -> Option<Result<T, RecvError>> {
+        try_iter_next(self.receiver.try_recv())
+    }
+}
+
+/// An owning iterator over the messages of an `IpcReceiver`,
+/// created by `IpcReceiver::into_iter()`. Behaves like `Iter`.
+#[derive(Debug)]
+pub struct IntoIter<T> where T: for<'de> Deserialize<'de> + Serialize {
+    receiver: IpcReceiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> where T: for<'de> Deserialize<'de> + Serialize {
+    type Item = Result<T, RecvError>;
+
+    fn next(&mut self) -> Option<Result<T, RecvError>> {
+        self.receiver.iter().next()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+    type Item = Result<T, RecvError>;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+    type Item = Result<T, RecvError>;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter {
+            receiver: self,
+        }
+    }
+}
+
+fn try_iter_next<T>(result: Result<T, TryRecvError>) -> Option<Result<T, RecvError>> {
+    match result {
+        Ok(data) => Some(Ok(data)),
+        Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        Err(TryRecvError::Io(err)) => Some(Err(RecvError::Io(err))),
+        Err(TryRecvError::Deserialize(err)) => Some(Err(RecvError::Deserialize(err))),
+    }
+}
+
 #[derive(Debug)]
 pub struct IpcSender<T> where T: Serialize {
     os_sender: OsIpcSender,
@@ -165,31 +366,82 @@ impl<T> IpcSender<T> where T: Serialize {
         })
     }
 
-    pub fn send(&self, data: T) -> Result<(), bincode::Error> {
-        let mut bytes = Vec::with_capacity(4096);
-        OS_IPC_CHANNELS_FOR_SERIALIZATION.with(|os_ipc_channels_for_serialization| {
-            OS_IPC_SHARED_MEMORY_REGIONS_FOR_SERIALIZATION.with(
-                    |os_ipc_shared_memory_regions_for_serialization| {
-                let old_os_ipc_channels =
-                    mem::replace(&mut *os_ipc_channels_for_serialization.borrow_mut(), Vec::new());
-                let old_os_ipc_shared_memory_regions =
-                    mem::replace(&mut *os_ipc_shared_memory_regions_for_serialization.borrow_mut(),
-                                 Vec::new());
-                let os_ipc_shared_memory_regions;
-                let os_ipc_channels;
-                {
-                    let mut serializer = bincode::Serializer::new(&mut bytes);
-                    data.serialize(&mut serializer)?;
-                    os_ipc_channels =
-                        mem::replace(&mut *os_ipc_channels_for_serialization.borrow_mut(),
-                                     old_os_ipc_channels);
-                    os_ipc_shared_memory_regions = mem::replace(
-                        &mut *os_ipc_shared_memory_regions_for_serialization.borrow_mut(),
-                        old_os_ipc_shared_memory_regions);
-                };
-                Ok(self.os_sender.send(&bytes[..], os_ipc_channels, os_ipc_shared_memory_regions)?)
-            })
-        })
+    /// Like `connect()`, but if there's no server listening on `name` yet, keeps trying
+    /// until `timeout` has passed. Useful when the server may not have been set up
+    /// by the time the client starts, e.g. in a freshly spawned process.
+    pub fn connect_timeout(name: String, timeout: Duration) -> Result<IpcSender<T>,Error> {
+        // A timeout too long to represent is as good as none.
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            match OsIpcSender::connect(name.clone()) {
+                Ok(os_sender) => {
+                    return Ok(IpcSender {
+                        os_sender: os_sender,
+                        phantom: PhantomData,
+                    })
+                }
+                Err(err) if err.is_not_listening() => {
+                    let mut delay = Duration::from_millis(10);
+                    if let Some(deadline) = deadline {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Err(Error::new(ErrorKind::TimedOut,
+                                                  "no server listening on the name"))
+                        }
+                        delay = min(deadline - now, delay);
+                    }
+                    thread::sleep(delay);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    pub fn send(&self, data: T) -> Result<(), SendError<T>> {
+        self.send_ref(&data).map_err(|kind| SendError::new(data, kind))
+    }
+
+    /// Like `send()`, but only borrows the value.
+    pub fn send_ref(&self, data: &T) -> Result<(), SendErrorKind> {
+        let (bytes, os_ipc_channels, os_ipc_shared_memory_regions) =
+            try!(serialize_message(data));
+        Ok(try!(self.os_sender.send(&bytes[..], os_ipc_channels, os_ipc_shared_memory_regions)))
+    }
+
+    /// Like `send_ref()`, but fails with `TrySendError::Full` instead of blocking.
+    /// Only borrowing the value leaves it with the caller, to retry.
+    ///
+    /// Messages too large to be sent in a single packet are sent all-or-nothing:
+    /// once the first part went through, sending the rest might still block.
+    pub fn try_send(&self, data: &T) -> Result<(), TrySendError> {
+        let (bytes, os_ipc_channels, os_ipc_shared_memory_regions) =
+            try!(serialize_message(data).map_err(TrySendError::Serialize));
+        Ok(try!(self.os_sender.try_send(&bytes[..],
+                                        os_ipc_channels,
+                                        os_ipc_shared_memory_regions)))
+    }
+
+    /// Like `send_ref()`, but gives up if blocked for longer than `duration`.
+    ///
+    /// The same caveat regarding large messages applies as for `try_send()`.
+    pub fn send_timeout(&self, data: &T, duration: Duration) -> Result<(), SendTimeoutError> {
+        let (bytes, os_ipc_channels, os_ipc_shared_memory_regions) =
+            try!(serialize_message(data).map_err(SendTimeoutError::Serialize));
+        Ok(try!(self.os_sender.send_timeout(&bytes[..],
+                                            os_ipc_channels,
+                                            os_ipc_shared_memory_regions,
+                                            duration)))
+    }
+
+    /// Sends a message prepared with `IpcMessage::new()`.
+    /// Its channels and shared memory regions are duplicated for every send.
+    pub fn send_message(&self, message: &IpcMessage<T>) -> Result<(), SendErrorKind> {
+        let os_ipc_channels = message.os_ipc_senders.iter().map(|os_ipc_sender| {
+            OsIpcChannel::Sender(os_ipc_sender.clone())
+        }).collect();
+        Ok(try!(self.os_sender.send(&message.data[..],
+                                    os_ipc_channels,
+                                    message.os_ipc_shared_memory_regions.clone())))
     }
 
     pub fn to_opaque(self) -> OpaqueIpcSender {
@@ -215,6 +467,266 @@ impl<T> Serialize for IpcSender<T> where T: Serialize {
     }
 }
 
+/// A message serialized once, to be sent any number of times with `IpcSender::send_message()`.
+pub struct IpcMessage<T> where T: Serialize {
+    data: Vec<u8>,
+    os_ipc_senders: Vec<OsIpcSender>,
+    os_ipc_shared_memory_regions: Vec<OsIpcSharedMemory>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> IpcMessage<T> where T: Serialize {
+    /// Serializes `data` into a message.
+    ///
+    /// Values containing receivers are rejected, as a receiver can only be sent once.
+    /// Any receivers in `data` are closed in that case.
+    pub fn new(data: &T) -> Result<IpcMessage<T>, bincode::Error> {
+        let (data, os_ipc_channels, os_ipc_shared_memory_regions) =
+            try!(serialize_message(data));
+        let mut os_ipc_senders = Vec::with_capacity(os_ipc_channels.len());
+        for os_ipc_channel in os_ipc_channels {
+            match os_ipc_channel {
+                OsIpcChannel::Sender(os_ipc_sender) => os_ipc_senders.push(os_ipc_sender),
+                OsIpcChannel::Receiver(_) => {
+                    return Err(ser::Error::custom("receivers can't be sent more than once"))
+                }
+            }
+        }
+        Ok(IpcMessage {
+            data: data,
+            os_ipc_senders: os_ipc_senders,
+            os_ipc_shared_memory_regions: os_ipc_shared_memory_regions,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// The sending half of a channel created with `sync_channel()`.
+///
+/// The receiver hands out a credit for every message it takes, and senders need one
+/// for every message they send. All senders of a channel share the same credit receiver,
+/// so the capacity is enforced across clones and processes alike.
+#[derive(Debug)]
+pub struct IpcSyncSender<T> where T: Serialize {
+    sender: IpcSender<T>,
+    capacity: usize,
+    credits: Arc<Mutex<Credits>>,
+}
+
+/// The credits available to a sender and its clones.
+#[derive(Debug)]
+struct Credits {
+    /// `None` once the sender has been moved to another process.
+    receiver: Option<IpcReceiver<()>>,
+    /// Credits taken for messages that then failed to send.
+    spare: usize,
+}
+
+impl<T> Clone for IpcSyncSender<T> where T: Serialize {
+    fn clone(&self) -> IpcSyncSender<T> {
+        IpcSyncSender {
+            sender: self.sender.clone(),
+            capacity: self.capacity,
+            credits: self.credits.clone(),
+        }
+    }
+}
+
+impl<T> IpcSyncSender<T> where T: Serialize {
+    fn new(sender: IpcSender<T>, capacity: usize, credit_receiver: IpcReceiver<()>)
+           -> IpcSyncSender<T> {
+        IpcSyncSender {
+            sender: sender,
+            capacity: capacity,
+            credits: Arc::new(Mutex::new(Credits {
+                receiver: Some(credit_receiver),
+                spare: 0,
+            })),
+        }
+    }
+
+    /// Sends a message, blocking while the channel is full.
+    ///
+    /// On failure, the value is handed back. On a rendezvous channel, that includes the case
+    /// where the receiver was dropped before taking it.
+    pub fn send(&self, data: T) -> Result<(), SendError<T>> {
+        self.send_ref(&data).map_err(|kind| SendError::new(data, kind))
+    }
+
+    /// Like `send()`, but only borrows the value.
+    pub fn send_ref(&self, data: &T) -> Result<(), SendErrorKind> {
+        if self.capacity > 0 {
+            try!(self.take_credit());
+        }
+        if let Err(err) = self.sender.send_ref(data) {
+            self.return_spare_credit();
+            return Err(err)
+        }
+        if self.capacity == 0 {
+            // Rendezvous channel: wait for the receiver to take a message.
+            try!(self.take_credit());
+        }
+        Ok(())
+    }
+
+    /// Sends a message if the channel isn't full, without blocking.
+    /// Like `send_ref()`, this only borrows the value.
+    ///
+    /// Whether a receiver is waiting can't be observed from here,
+    /// so on a rendezvous channel (with a capacity of 0) this always fails with `Full`.
+    pub fn try_send(&self, data: &T) -> Result<(), TrySendError> {
+        if self.capacity == 0 {
+            return Err(TrySendError::Full)
+        }
+        {
+            // If a clone is already waiting for a credit, there is none to be had.
+            let mut credits = match self.credits.try_lock() {
+                Ok(credits) => credits,
+                Err(_) => return Err(TrySendError::Full),
+            };
+            if credits.spare > 0 {
+                credits.spare -= 1;
+            } else {
+                match credits.receiver.as_ref().map(|receiver| receiver.try_recv()) {
+                    Some(Ok(())) | Some(Err(TryRecvError::Deserialize(_))) => {}
+                    Some(Err(TryRecvError::Empty)) => return Err(TrySendError::Full),
+                    Some(Err(TryRecvError::Io(err))) => return Err(TrySendError::Io(err)),
+                    Some(Err(TryRecvError::Disconnected)) | None => {
+                        return Err(TrySendError::Disconnected)
+                    }
+                }
+            }
+        }
+        if let Err(err) = self.sender.send_ref(data) {
+            self.return_spare_credit();
+            return Err(TrySendError::from(err))
+        }
+        Ok(())
+    }
+
+    /// Waits for a credit from the receiver.
+    fn take_credit(&self) -> Result<(), SendErrorKind> {
+        let mut credits = self.credits.lock().unwrap();
+        if credits.spare > 0 {
+            credits.spare -= 1;
+            return Ok(())
+        }
+        match credits.receiver.as_ref().map(|receiver| receiver.recv()) {
+            Some(Ok(())) | Some(Err(RecvError::Deserialize(_))) => Ok(()),
+            Some(Err(RecvError::Io(err))) => Err(SendErrorKind::Io(err)),
+            Some(Err(RecvError::Disconnected)) | None => Err(SendErrorKind::Disconnected),
+        }
+    }
+
+    /// Keeps the credit taken for a message that couldn't be sent.
+    fn return_spare_credit(&self) {
+        if self.capacity > 0 {
+            self.credits.lock().unwrap().spare += 1;
+        }
+    }
+
+    /// A credit receiver for a copy of this sender in another process.
+    ///
+    /// Where the platform can't share a receiver, this only works if there are no clones;
+    /// the credits then move along, and this sender stops working.
+    fn transfer_credits(&self) -> Result<IpcReceiver<()>, Error> {
+        let mut credits = self.credits.lock().unwrap();
+        let cloned = match credits.receiver {
+            Some(ref receiver) => receiver.try_clone(),
+            None => return Err(Error::new(ErrorKind::Other, "sender was moved to another process")),
+        };
+        match cloned {
+            Ok(receiver) => Ok(receiver),
+            Err(_) if Arc::strong_count(&self.credits) == 1 => Ok(credits.receiver.take().unwrap()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<'de, T> Deserialize<'de> for IpcSyncSender<T> where T: Serialize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let (capacity, sender, credit_receiver) = try!(Deserialize::deserialize(deserializer));
+        Ok(IpcSyncSender::new(sender, capacity, credit_receiver))
+    }
+}
+
+impl<T> Serialize for IpcSyncSender<T> where T: Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let credit_receiver = try!(self.transfer_credits().map_err(ser::Error::custom));
+        (self.capacity, &self.sender, credit_receiver).serialize(serializer)
+    }
+}
+
+/// The receiving half of a channel created with `sync_channel()`.
+#[derive(Debug)]
+pub struct IpcSyncReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+    receiver: IpcReceiver<T>,
+    credit_sender: IpcSender<()>,
+    /// Credits not handed out yet, because the credit channel was full.
+    pending_credits: Cell<usize>,
+}
+
+impl<T> IpcSyncReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let data = try!(self.receiver.recv());
+        Ok(self.taken(data))
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let data = try!(self.receiver.try_recv());
+        Ok(self.taken(data))
+    }
+
+    /// Blocks for at most `duration` waiting for a message.
+    pub fn recv_timeout(&self, duration: Duration) -> Result<T, RecvTimeoutError> {
+        let data = try!(self.receiver.recv_timeout(duration));
+        Ok(self.taken(data))
+    }
+
+    /// Like `recv_timeout()`, but waits until the given point in time.
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let data = try!(self.receiver.recv_deadline(deadline));
+        Ok(self.taken(data))
+    }
+
+    fn taken(&self, data: T) -> T {
+        self.pending_credits.set(self.pending_credits.get() + 1);
+        self.return_credits();
+        data
+    }
+
+    /// Hands out as many pending credits as fit into the credit channel without blocking.
+    /// The rest go out as further messages are taken.
+    fn return_credits(&self) {
+        while self.pending_credits.get() > 0 {
+            // If all senders are gone, nobody needs the credits anyway.
+            if self.credit_sender.try_send(&()).is_err() {
+                return
+            }
+            self.pending_credits.set(self.pending_credits.get() - 1);
+        }
+    }
+}
+
+impl<'de, T> Deserialize<'de> for IpcSyncReceiver<T>
+                                where T: for<'dde> Deserialize<'dde> + Serialize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let (receiver, credit_sender, pending_credits) =
+            try!(Deserialize::deserialize(deserializer));
+        Ok(IpcSyncReceiver {
+            receiver: receiver,
+            credit_sender: credit_sender,
+            pending_credits: Cell::new(pending_credits),
+        })
+    }
+}
+
+impl<T> Serialize for IpcSyncReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        (&self.receiver, &self.credit_sender, self.pending_credits.get()).serialize(serializer)
+    }
+}
+
 pub struct IpcReceiverSet {
     os_receiver_set: OsIpcReceiverSet,
 }
@@ -235,29 +747,139 @@ impl IpcReceiverSet {
         Ok(try!(self.os_receiver_set.add(receiver.os_receiver)))
     }
 
+    /// Stops listening on the receiver with the given `id`, handing it back to the caller.
+    ///
+    /// Returns `None` if there is no such receiver in the set,
+    /// e.g. because it has already been reported as closed,
+    /// or if the OS fails to stop listening on it; it then stays in the set.
+    pub fn remove(&mut self, id: u64) -> Option<OpaqueIpcReceiver> {
+        self.os_receiver_set.remove(id).map(|os_receiver| {
+            OpaqueIpcReceiver {
+                os_receiver: os_receiver,
+            }
+        })
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.os_receiver_set.contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.os_receiver_set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn select(&mut self) -> Result<Vec<IpcSelectionResult>,Error> {
         let results = try!(self.os_receiver_set.select());
-        Ok(results.into_iter().map(|result| {
+        Ok(results.into_iter().map(IpcSelectionResult::from_os).collect())
+    }
+
+    /// Like `select()`, but returns an empty result instead of blocking.
+    pub fn try_select(&mut self) -> Result<Vec<IpcSelectionResult>,Error> {
+        let results = try!(self.os_receiver_set.try_select());
+        Ok(results.into_iter().map(IpcSelectionResult::from_os).collect())
+    }
+
+    /// Like `select()`, but returns an empty result if nothing arrived within `duration`.
+    pub fn select_timeout(&mut self, duration: Duration) -> Result<Vec<IpcSelectionResult>,Error> {
+        let results = try!(self.os_receiver_set.select_timeout(duration));
+        Ok(results.into_iter().map(IpcSelectionResult::from_os).collect())
+    }
+}
+
+/// A typed layer over `IpcReceiverSet`.
+///
+/// Each receiver is registered along with a handler, which gets passed the messages
+/// already decoded into the receiver's type.
+pub struct IpcSelector<'a> {
+    receiver_set: IpcReceiverSet,
+    handlers: HashMap<u64, Box<FnMut(Option<OpaqueIpcMessage>) + 'a>>,
+}
+
+impl<'a> IpcSelector<'a> {
+    pub fn new() -> Result<IpcSelector<'a>,Error> {
+        Ok(IpcSelector {
+            receiver_set: try!(IpcReceiverSet::new()),
+            handlers: HashMap::new(),
+        })
+    }
+
+    /// Registers `receiver`, with `handler` getting called for every message received on it.
+    ///
+    /// Once all senders have been dropped, `handler` is called a last time with
+    /// `Err(RecvError::Disconnected)`, and the receiver is removed from the selector.
+    pub fn add<T, F>(&mut self, receiver: IpcReceiver<T>, mut handler: F) -> Result<u64,Error>
+                     where T: for<'de> Deserialize<'de> + Serialize,
+                           F: FnMut(Result<T, RecvError>) + 'a {
+        let id = try!(self.receiver_set.add(receiver));
+        self.handlers.insert(id, Box::new(move |message: Option<OpaqueIpcMessage>| {
+            handler(match message {
+                Some(message) => message.to().map_err(RecvError::Deserialize),
+                None => Err(RecvError::Disconnected),
+            })
+        }));
+        Ok(id)
+    }
+
+    /// Stops listening on the receiver with the given `id`, dropping its handler,
+    /// and handing the receiver back to the caller.
+    pub fn remove(&mut self, id: u64) -> Option<OpaqueIpcReceiver> {
+        self.handlers.remove(&id);
+        self.receiver_set.remove(id)
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.receiver_set.contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.receiver_set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver_set.is_empty()
+    }
+
+    /// Waits for messages, passing them on to the respective handlers.
+    ///
+    /// Returns the number of events handled.
+    pub fn select(&mut self) -> Result<usize,Error> {
+        let results = try!(self.receiver_set.select());
+        Ok(self.dispatch(results))
+    }
+
+    /// Like `select()`, but returns 0 instead of blocking.
+    pub fn try_select(&mut self) -> Result<usize,Error> {
+        let results = try!(self.receiver_set.try_select());
+        Ok(self.dispatch(results))
+    }
+
+    /// Like `select()`, but returns 0 if nothing arrived within `duration`.
+    pub fn select_timeout(&mut self, duration: Duration) -> Result<usize,Error> {
+        let results = try!(self.receiver_set.select_timeout(duration));
+        Ok(self.dispatch(results))
+    }
+
+    fn dispatch(&mut self, results: Vec<IpcSelectionResult>) -> usize {
+        let count = results.len();
+        for result in results.into_iter() {
             match result {
-                OsIpcSelectionResult::DataReceived(os_receiver_id,
-                                                   data,
-                                                   os_ipc_channels,
-                                                   os_ipc_shared_memory_regions) => {
-                    IpcSelectionResult::MessageReceived(os_receiver_id, OpaqueIpcMessage {
-                        data: data,
-                        os_ipc_channels: os_ipc_channels,
-                        os_ipc_shared_memory_regions:
-                            os_ipc_shared_memory_regions.into_iter().map(
-                                |os_ipc_shared_memory_region| {
-                                    Some(os_ipc_shared_memory_region)
-                                }).collect(),
-                    })
+                IpcSelectionResult::MessageReceived(id, message) => {
+                    if let Some(handler) = self.handlers.get_mut(&id) {
+                        handler(Some(message))
+                    }
                 }
-                OsIpcSelectionResult::ChannelClosed(os_receiver_id) => {
-                    IpcSelectionResult::ChannelClosed(os_receiver_id)
+                IpcSelectionResult::ChannelClosed(id) => {
+                    if let Some(mut handler) = self.handlers.remove(&id) {
+                        handler(None)
+                    }
                 }
             }
-        }).collect())
+        }
+        count
     }
 }
 
@@ -278,14 +900,17 @@ impl Deref for IpcSharedMemory {
 impl<'de> Deserialize<'de> for IpcSharedMemory {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
         let index: usize = try!(Deserialize::deserialize(deserializer));
-        let os_shared_memory = OS_IPC_SHARED_MEMORY_REGIONS_FOR_DESERIALIZATION.with(
+        let os_shared_memory = try!(OS_IPC_SHARED_MEMORY_REGIONS_FOR_DESERIALIZATION.with(
             |os_ipc_shared_memory_regions_for_deserialization| {
-                // FIXME(pcwalton): This could panic if the data was corrupt and the index was out
-                // of bounds. We should return an `Err` result instead.
-                mem::replace(
-                    &mut os_ipc_shared_memory_regions_for_deserialization.borrow_mut()[index],
-                    None).unwrap()
-            });
+                match os_ipc_shared_memory_regions_for_deserialization.borrow_mut().get_mut(index) {
+                    Some(region) => region.take().ok_or_else(|| {
+                        de::Error::custom(format!("shared memory region {} already claimed",
+                                                  index))
+                    }),
+                    None => Err(de::Error::custom(format!("shared memory index {} out of range",
+                                                          index))),
+                }
+            }));
         Ok(IpcSharedMemory {
             os_shared_memory: os_shared_memory,
         })
@@ -327,6 +952,24 @@ pub enum IpcSelectionResult {
 }
 
 impl IpcSelectionResult {
+    fn from_os(result: OsIpcSelectionResult) -> IpcSelectionResult {
+        match result {
+            OsIpcSelectionResult::DataReceived(os_receiver_id,
+                                               data,
+                                               os_ipc_channels,
+                                               os_ipc_shared_memory_regions) => {
+                IpcSelectionResult::MessageReceived(os_receiver_id,
+                                                    OpaqueIpcMessage::new(
+                                                        data,
+                                                        os_ipc_channels,
+                                                        os_ipc_shared_memory_regions))
+            }
+            OsIpcSelectionResult::ChannelClosed(os_receiver_id) => {
+                IpcSelectionResult::ChannelClosed(os_receiver_id)
+            }
+        }
+    }
+
     pub fn unwrap(self) -> (u64, OpaqueIpcMessage) {
         match self {
             IpcSelectionResult::MessageReceived(id, message) => (id, message),
@@ -368,24 +1011,53 @@ impl OpaqueIpcMessage {
         }
     }
 
-    pub fn to<T>(mut self) -> Result<T, bincode::Error> where T: for<'de> Deserialize<'de> + Serialize {
-        OS_IPC_CHANNELS_FOR_DESERIALIZATION.with(|os_ipc_channels_for_deserialization| {
-            OS_IPC_SHARED_MEMORY_REGIONS_FOR_DESERIALIZATION.with(
-                    |os_ipc_shared_memory_regions_for_deserialization| {
-                mem::swap(&mut *os_ipc_channels_for_deserialization.borrow_mut(),
-                          &mut self.os_ipc_channels);
-                mem::swap(&mut *os_ipc_shared_memory_regions_for_deserialization.borrow_mut(),
-                          &mut self.os_ipc_shared_memory_regions);
-                let result = bincode::deserialize(&self.data[..]);
-                mem::swap(&mut *os_ipc_shared_memory_regions_for_deserialization.borrow_mut(),
-                          &mut self.os_ipc_shared_memory_regions);
-                mem::swap(&mut *os_ipc_channels_for_deserialization.borrow_mut(),
-                          &mut self.os_ipc_channels);
-                /* Error check comes after doing cleanup,
-                 * since we need the cleanup both in the success and the error cases. */
-                Ok(result?)
-            })
-        })
+    /// The serialized payload of the message.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    /// The number of channels transferred with the message.
+    pub fn channel_count(&self) -> usize {
+        self.os_ipc_channels.len()
+    }
+
+    /// The number of shared memory regions transferred with the message.
+    pub fn shared_memory_region_count(&self) -> usize {
+        self.os_ipc_shared_memory_regions.len()
+    }
+
+    pub fn to<T>(self) -> Result<T, bincode::Error> where T: for<'de> Deserialize<'de> + Serialize {
+        self.to_with_unclaimed().0
+    }
+
+    /// Like `to()`, but also returns the number of channels and shared memory regions
+    /// transferred with the message that were not claimed by the deserialized value.
+    ///
+    /// Unclaimed channels and shared memory regions are released before this returns.
+    pub fn to_with_unclaimed<T>(mut self) -> (Result<T, bincode::Error>, usize)
+                                where T: for<'de> Deserialize<'de> + Serialize {
+        let result =
+            OS_IPC_CHANNELS_FOR_DESERIALIZATION.with(|os_ipc_channels_for_deserialization| {
+                OS_IPC_SHARED_MEMORY_REGIONS_FOR_DESERIALIZATION.with(
+                        |os_ipc_shared_memory_regions_for_deserialization| {
+                    mem::swap(&mut *os_ipc_channels_for_deserialization.borrow_mut(),
+                              &mut self.os_ipc_channels);
+                    mem::swap(&mut *os_ipc_shared_memory_regions_for_deserialization.borrow_mut(),
+                              &mut self.os_ipc_shared_memory_regions);
+                    let result = bincode::deserialize(&self.data[..]);
+                    mem::swap(&mut *os_ipc_shared_memory_regions_for_deserialization.borrow_mut(),
+                              &mut self.os_ipc_shared_memory_regions);
+                    mem::swap(&mut *os_ipc_channels_for_deserialization.borrow_mut(),
+                              &mut self.os_ipc_channels);
+                    result
+                })
+            });
+        (result, self.unclaimed_count())
+    }
+
+    fn unclaimed_count(&self) -> usize {
+        self.os_ipc_channels.iter().filter(|channel| !channel.is_consumed()).count() +
+            self.os_ipc_shared_memory_regions.iter().filter(|region| region.is_some()).count()
     }
 }
 
@@ -401,6 +1073,20 @@ impl OpaqueIpcSender {
             phantom: PhantomData,
         }
     }
+
+    /// Sends a received message on as it is, without deserializing it.
+    /// Its channels and shared memory regions are passed on, rather than duplicated.
+    pub fn send_opaque(&self, message: OpaqueIpcMessage) -> Result<(), SendErrorKind> {
+        // Messages are only ever claimed as a whole, so all the channels and regions are there.
+        let os_ipc_channels = message.os_ipc_channels.into_iter().map(|mut os_ipc_channel| {
+            os_ipc_channel.to_channel().unwrap()
+        }).collect();
+        let os_ipc_shared_memory_regions =
+            message.os_ipc_shared_memory_regions.into_iter().map(Option::unwrap).collect();
+        Ok(try!(self.os_sender.send(&message.data[..],
+                                    os_ipc_channels,
+                                    os_ipc_shared_memory_regions)))
+    }
 }
 
 impl<'de> Deserialize<'de> for OpaqueIpcSender {
@@ -423,31 +1109,53 @@ pub struct OpaqueIpcReceiver {
     os_receiver: OsIpcReceiver,
 }
 
-pub struct IpcOneShotServer<T> {
-    os_server: OsIpcOneShotServer,
-    phantom: PhantomData<T>,
-}
-
+impl OpaqueIpcReceiver {
+    pub fn to<T>(self) -> IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+        IpcReceiver {
+            os_receiver: self.os_receiver,
+            phantom: PhantomData,
+        }
+    }
+}
+
+pub struct IpcOneShotServer<T> {
+    os_server: OsIpcOneShotServer,
+    peer_policy: PeerPolicy,
+    phantom: PhantomData<T>,
+}
+
 impl<T> IpcOneShotServer<T> where T: for<'de> Deserialize<'de> + Serialize {
     pub fn new() -> Result<(IpcOneShotServer<T>, String),Error> {
-        let (os_server, name) = try!(OsIpcOneShotServer::new());
+        IpcOneShotServer::with_options(&Default::default())
+    }
+
+    /// Like `new()`, but listening where and how `options` say.
+    ///
+    /// Any socket file is removed once a client has connected, or when the server is dropped.
+    pub fn with_options(options: &IpcServerOptions) -> Result<(IpcOneShotServer<T>, String),Error> {
+        let (os_server, name) = try!(OsIpcOneShotServer::with_options(options));
         Ok((IpcOneShotServer {
             os_server: os_server,
+            peer_policy: PeerPolicy::Any,
             phantom: PhantomData,
         }, name))
     }
 
-    pub fn accept(self) -> Result<(IpcReceiver<T>,T), bincode::Error> {
+    /// Sets which client to accept. By default, that's any client.
+    ///
+    /// Rejected clients are disconnected without reading anything they sent,
+    /// and the server keeps waiting for another one. On macOS, where credentials
+    /// aren't available, any policy but `Any` makes `accept()` fail.
+    pub fn set_peer_policy(&mut self, peer_policy: PeerPolicy) {
+        self.peer_policy = peer_policy
+    }
+
+    pub fn accept(self) -> Result<(IpcReceiver<T>,T), RecvError> {
+        let peer_policy = self.peer_policy;
         let (os_receiver, data, os_channels, os_shared_memory_regions) =
-            try!(self.os_server.accept());
-        let value = try!(OpaqueIpcMessage {
-            data: data,
-            os_ipc_channels: os_channels,
-            os_ipc_shared_memory_regions: os_shared_memory_regions.into_iter()
-                                                                  .map(|os_shared_memory_region| {
-                Some(os_shared_memory_region)
-            }).collect(),
-        }.to());
+            try!(self.os_server.accept_filtered(|os_receiver| peer_policy.allows(os_receiver)));
+        let value = try!(OpaqueIpcMessage::new(data, os_channels, os_shared_memory_regions)
+                             .to().map_err(RecvError::Deserialize));
         Ok((IpcReceiver {
             os_receiver: os_receiver,
             phantom: PhantomData,
@@ -455,6 +1163,147 @@ impl<T> IpcOneShotServer<T> where T: for<'de> Deserialize<'de> + Serialize {
     }
 }
 
+/// Where and how a server listens for clients.
+///
+/// These only apply to Unix sockets; the other backends have no filesystem entry
+/// to configure, and ignore them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IpcServerOptions {
+    /// The directory to create the socket in, instead of `/tmp`.
+    pub directory: Option<PathBuf>,
+    /// The permissions of the socket file, such as `0o600`, instead of those left by the umask.
+    pub mode: Option<u32>,
+    /// Whether to use a name in the abstract socket namespace, which has no filesystem entry.
+    /// Such names start with a NUL character. Only supported on Linux.
+    pub abstract_namespace: bool,
+}
+
+/// A server that any number of clients can connect to with `IpcSender::connect()`,
+/// unlike `IpcOneShotServer`.
+///
+/// Clients don't need to send anything to be accepted. Their messages can be read
+/// from the returned receivers, for example with an `IpcReceiverSet` or the router.
+/// Not available on macOS.
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+pub struct IpcServer<T> {
+    os_server: OsIpcServer,
+    peer_policy: PeerPolicy,
+    phantom: PhantomData<T>,
+}
+
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+impl<T> IpcServer<T> where T: for<'de> Deserialize<'de> + Serialize {
+    pub fn new() -> Result<(IpcServer<T>, String),Error> {
+        IpcServer::with_options(&Default::default())
+    }
+
+    /// Like `new()`, but listening where and how `options` say.
+    ///
+    /// Any socket file is removed when the server is dropped.
+    pub fn with_options(options: &IpcServerOptions) -> Result<(IpcServer<T>, String),Error> {
+        let (os_server, name) = try!(OsIpcServer::with_options(options));
+        Ok((IpcServer {
+            os_server: os_server,
+            peer_policy: PeerPolicy::Any,
+            phantom: PhantomData,
+        }, name))
+    }
+
+    /// Sets which clients to accept. By default, that's any client.
+    pub fn set_peer_policy(&mut self, peer_policy: PeerPolicy) {
+        self.peer_policy = peer_policy
+    }
+
+    /// Waits for the next client to connect, returning a receiver for its messages.
+    /// Use `IpcReceiver::peer_credentials()` to find out who the client is.
+    ///
+    /// Clients rejected by the peer policy are disconnected right away,
+    /// without reading anything they sent.
+    pub fn accept(&self) -> Result<IpcReceiver<T>,Error> {
+        loop {
+            let os_receiver = try!(self.os_server.accept());
+            if self.peer_policy.allows(&os_receiver) {
+                return Ok(IpcReceiver {
+                    os_receiver: os_receiver,
+                    phantom: PhantomData,
+                })
+            }
+        }
+    }
+
+    /// An iterator accepting clients as they connect. It never ends by itself.
+    pub fn incoming(&self) -> Incoming<T> {
+        Incoming {
+            server: self,
+        }
+    }
+}
+
+/// An iterator over the clients of an `IpcServer`, see `IpcServer::incoming()`.
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+pub struct Incoming<'a, T: 'a> {
+    server: &'a IpcServer<T>,
+}
+
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+impl<'a, T> Iterator for Incoming<'a, T> where T: for<'de> Deserialize<'de> + Serialize {
+    type Item = Result<IpcReceiver<T>,Error>;
+
+    fn next(&mut self) -> Option<Result<IpcReceiver<T>,Error>> {
+        Some(self.server.accept())
+    }
+}
+
+/// The credentials of a process, see `IpcReceiver::peer_credentials()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerCredentials {
+    /// The process ID, where the platform reports it.
+    pub pid: Option<u32>,
+    /// The effective user ID.
+    pub uid: u32,
+    /// The effective group ID.
+    pub gid: u32,
+}
+
+/// Which clients an `IpcServer` or `IpcOneShotServer` accepts.
+///
+/// Clients whose credentials can't be determined are only accepted with `Any`.
+pub enum PeerPolicy {
+    /// Accept any client.
+    Any,
+    /// Only accept clients running as the same effective user as this process.
+    SameUser,
+    /// Only accept clients for which the callback returns `true`.
+    Allow(Box<Fn(&PeerCredentials) -> bool + Send + Sync>),
+}
+
+impl PeerPolicy {
+    fn allows(&self, os_receiver: &OsIpcReceiver) -> bool {
+        if let PeerPolicy::Any = *self {
+            return true
+        }
+        let credentials = match os_receiver.peer_credentials() {
+            Ok(credentials) => credentials,
+            Err(_) => return false,
+        };
+        match *self {
+            PeerPolicy::Any => true,
+            PeerPolicy::SameUser => Some(credentials.uid) == effective_uid(),
+            PeerPolicy::Allow(ref allow) => allow(&credentials),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn effective_uid() -> Option<u32> {
+    Some(unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+fn effective_uid() -> Option<u32> {
+    None
+}
+
 #[derive(Debug)]
 pub struct IpcBytesReceiver {
     os_receiver: OsIpcReceiver,
@@ -462,23 +1311,126 @@ pub struct IpcBytesReceiver {
 
 impl IpcBytesReceiver {
     #[inline]
-    pub fn recv(&self) -> Result<Vec<u8>, bincode::Error> {
+    pub fn recv(&self) -> Result<Vec<u8>, RecvError> {
         match self.os_receiver.recv() {
             Ok((data, _, _)) => Ok(data),
             Err(err) => Err(err.into()),
         }
     }
+
+    #[inline]
+    pub fn try_recv(&self) -> Result<Vec<u8>, TryRecvError> {
+        match self.os_receiver.try_recv() {
+            Ok((data, _, _)) => Ok(data),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Blocks for at most `duration` waiting for a message.
+    #[inline]
+    pub fn recv_timeout(&self, duration: Duration) -> Result<Vec<u8>, RecvTimeoutError> {
+        match self.os_receiver.recv_timeout(duration) {
+            Ok((data, _, _)) => Ok(data),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Like `recv_timeout()`, but waits until the given point in time.
+    #[inline]
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<Vec<u8>, RecvTimeoutError> {
+        match self.os_receiver.recv_deadline(deadline) {
+            Ok((data, _, _)) => Ok(data),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Returns an iterator that blocks waiting for messages,
+    /// ending once all senders have been dropped.
+    pub fn iter(&self) -> BytesIter {
+        BytesIter {
+            receiver: self,
+        }
+    }
+
+    /// Returns an iterator over the messages currently pending, without blocking.
+    pub fn try_iter(&self) -> BytesTryIter {
+        BytesTryIter {
+            receiver: self,
+        }
+    }
+}
+
+/// An iterator over the messages of an `IpcBytesReceiver`,
+/// created by `IpcBytesReceiver::iter()`. Behaves like `Iter`.
+#[derive(Debug)]
+pub struct BytesIter<'a> {
+    receiver: &'a IpcBytesReceiver,
+}
+
+impl<'a> Iterator for BytesIter<'a> {
+    type Item = Result<Vec<u8>, RecvError>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, RecvError>> {
+        match self.receiver.recv() {
+            Err(RecvError::Disconnected) => None,
+            result => Some(result),
+        }
+    }
+}
+
+/// An iterator over the pending messages of an `IpcBytesReceiver`,
+/// created by `IpcBytesReceiver::try_iter()`. Behaves like `TryIter`.
+#[derive(Debug)]
+pub struct BytesTryIter<'a> {
+    receiver: &'a IpcBytesReceiver,
+}
+
+impl<'a> Iterator for BytesTryIter<'a> {
+    type Item = Result<Vec<u8>, RecvError>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, RecvError>> {
+        try_iter_next(self.receiver.try_recv())
+    }
+}
+
+/// An owning iterator over the messages of an `IpcBytesReceiver`,
+/// created by `IpcBytesReceiver::into_iter()`. Behaves like `Iter`.
+#[derive(Debug)]
+pub struct BytesIntoIter {
+    receiver: IpcBytesReceiver,
+}
+
+impl Iterator for BytesIntoIter {
+    type Item = Result<Vec<u8>, RecvError>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, RecvError>> {
+        self.receiver.iter().next()
+    }
+}
+
+impl<'a> IntoIterator for &'a IpcBytesReceiver {
+    type Item = Result<Vec<u8>, RecvError>;
+    type IntoIter = BytesIter<'a>;
+
+    fn into_iter(self) -> BytesIter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for IpcBytesReceiver {
+    type Item = Result<Vec<u8>, RecvError>;
+    type IntoIter = BytesIntoIter;
+
+    fn into_iter(self) -> BytesIntoIter {
+        BytesIntoIter {
+            receiver: self,
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for IpcBytesReceiver {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
-        let index: usize = try!(Deserialize::deserialize(deserializer));
-        let os_receiver =
-            OS_IPC_CHANNELS_FOR_DESERIALIZATION.with(|os_ipc_channels_for_deserialization| {
-                // FIXME(pcwalton): This could panic if the data was corrupt and the index was out
-                // of bounds. We should return an `Err` result instead.
-                os_ipc_channels_for_deserialization.borrow_mut()[index].to_receiver()
-            });
+        let os_receiver = try!(deserialize_os_ipc_receiver(deserializer));
         Ok(IpcBytesReceiver {
             os_receiver: os_receiver,
         })
@@ -529,11 +1481,54 @@ impl Serialize for IpcBytesSender {
 
 impl IpcBytesSender {
     #[inline]
-    pub fn send(&self, data: &[u8]) -> Result<(),Error> {
-        self.os_sender.send(data, vec![], vec![]).map_err(|e| Error::from(e))
+    pub fn send(&self, data: &[u8]) -> Result<(),SendErrorKind> {
+        self.os_sender.send(data, vec![], vec![]).map_err(SendErrorKind::from)
+    }
+
+    /// Like `send()`, but fails with `TrySendError::Full` instead of blocking.
+    ///
+    /// See `IpcSender::try_send()` regarding large messages.
+    pub fn try_send(&self, data: &[u8]) -> Result<(),TrySendError> {
+        self.os_sender.try_send(data, vec![], vec![]).map_err(TrySendError::from)
+    }
+
+    /// Like `send()`, but gives up if blocked for longer than `duration`.
+    pub fn send_timeout(&self, data: &[u8], duration: Duration) -> Result<(),SendTimeoutError> {
+        self.os_sender.send_timeout(data, vec![], vec![], duration).map_err(SendTimeoutError::from)
     }
 }
 
+/// Serialize `data`, collecting the channels and shared memory regions embedded in it.
+fn serialize_message<T>(data: &T)
+                        -> Result<(Vec<u8>, Vec<OsIpcChannel>, Vec<OsIpcSharedMemory>),
+                                  bincode::Error>
+                        where T: Serialize {
+    let mut bytes = Vec::with_capacity(4096);
+    OS_IPC_CHANNELS_FOR_SERIALIZATION.with(|os_ipc_channels_for_serialization| {
+        OS_IPC_SHARED_MEMORY_REGIONS_FOR_SERIALIZATION.with(
+                |os_ipc_shared_memory_regions_for_serialization| {
+            let old_os_ipc_channels =
+                mem::replace(&mut *os_ipc_channels_for_serialization.borrow_mut(), Vec::new());
+            let old_os_ipc_shared_memory_regions =
+                mem::replace(&mut *os_ipc_shared_memory_regions_for_serialization.borrow_mut(),
+                             Vec::new());
+            let os_ipc_shared_memory_regions;
+            let os_ipc_channels;
+            {
+                let mut serializer = bincode::Serializer::new(&mut bytes);
+                data.serialize(&mut serializer)?;
+                os_ipc_channels =
+                    mem::replace(&mut *os_ipc_channels_for_serialization.borrow_mut(),
+                                 old_os_ipc_channels);
+                os_ipc_shared_memory_regions = mem::replace(
+                    &mut *os_ipc_shared_memory_regions_for_serialization.borrow_mut(),
+                    old_os_ipc_shared_memory_regions);
+            };
+            Ok((bytes, os_ipc_channels, os_ipc_shared_memory_regions))
+        })
+    })
+}
+
 fn serialize_os_ipc_sender<S>(os_ipc_sender: &OsIpcSender, serializer: S)
                               -> Result<S::Ok, S::Error> where S: Serializer {
     let index = OS_IPC_CHANNELS_FOR_SERIALIZATION.with(|os_ipc_channels_for_serialization| {
@@ -550,8 +1545,520 @@ fn deserialize_os_ipc_sender<'de, D>(deserializer: D)
                                 -> Result<OsIpcSender, D::Error> where D: Deserializer<'de> {
     let index: usize = try!(Deserialize::deserialize(deserializer));
     OS_IPC_CHANNELS_FOR_DESERIALIZATION.with(|os_ipc_channels_for_deserialization| {
-        // FIXME(pcwalton): This could panic if the data was corrupt and the index was out of
-        // bounds. We should return an `Err` result instead.
-        Ok(os_ipc_channels_for_deserialization.borrow_mut()[index].to_sender())
+        match os_ipc_channels_for_deserialization.borrow_mut().get_mut(index) {
+            Some(channel) => channel.try_to_sender().ok_or_else(|| {
+                de::Error::custom(format!("channel {} is not an unclaimed sender", index))
+            }),
+            None => Err(de::Error::custom(format!("channel index {} out of range", index))),
+        }
     })
 }
+
+fn deserialize_os_ipc_receiver<'de, D>(deserializer: D)
+                                  -> Result<OsIpcReceiver, D::Error> where D: Deserializer<'de> {
+    let index: usize = try!(Deserialize::deserialize(deserializer));
+    OS_IPC_CHANNELS_FOR_DESERIALIZATION.with(|os_ipc_channels_for_deserialization| {
+        match os_ipc_channels_for_deserialization.borrow_mut().get_mut(index) {
+            Some(channel) => channel.try_to_receiver().ok_or_else(|| {
+                de::Error::custom(format!("channel {} is not an unclaimed receiver", index))
+            }),
+            None => Err(de::Error::custom(format!("channel index {} out of range", index))),
+        }
+    })
+}
+
+/// Conversions from and to raw file descriptors, with the Unix socket backend.
+///
+/// `into_raw_fd()` hands over ownership of the file descriptor, and `from_raw_fd()` takes it.
+/// The file descriptor of a sender is shared with its clones, so if there are any left,
+/// `into_raw_fd()` returns a duplicate. Converting shared memory unmaps it;
+/// converting it back maps the whole file.
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+mod raw_fd {
+    use platform::{OsIpcReceiver, OsIpcSender, OsIpcSharedMemory};
+    use serde::{Deserialize, Serialize};
+    use std::marker::PhantomData;
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+    use super::{IpcBytesReceiver, IpcBytesSender, IpcReceiver, IpcSender, IpcSharedMemory};
+    use super::{OpaqueIpcReceiver, OpaqueIpcSender};
+
+    impl<T> AsRawFd for IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_receiver.as_raw_fd()
+        }
+    }
+
+    impl<T> IntoRawFd for IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+        fn into_raw_fd(self) -> RawFd {
+            self.os_receiver.into_raw_fd()
+        }
+    }
+
+    impl<T> FromRawFd for IpcReceiver<T> where T: for<'de> Deserialize<'de> + Serialize {
+        unsafe fn from_raw_fd(fd: RawFd) -> IpcReceiver<T> {
+            IpcReceiver {
+                os_receiver: OsIpcReceiver::from_raw_fd(fd),
+                phantom: PhantomData,
+            }
+        }
+    }
+
+    impl<T> AsRawFd for IpcSender<T> where T: Serialize {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_sender.as_raw_fd()
+        }
+    }
+
+    impl<T> IntoRawFd for IpcSender<T> where T: Serialize {
+        /// If any clones of this sender are left, this returns a duplicate of its
+        /// file descriptor, so not the number `as_raw_fd()` returns.
+        ///
+        /// # Panics
+        ///
+        /// If duplicating the file descriptor fails.
+        fn into_raw_fd(self) -> RawFd {
+            self.os_sender.into_raw_fd()
+        }
+    }
+
+    impl<T> FromRawFd for IpcSender<T> where T: Serialize {
+        unsafe fn from_raw_fd(fd: RawFd) -> IpcSender<T> {
+            IpcSender {
+                os_sender: OsIpcSender::from_raw_fd(fd),
+                phantom: PhantomData,
+            }
+        }
+    }
+
+    impl AsRawFd for IpcBytesReceiver {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_receiver.as_raw_fd()
+        }
+    }
+
+    impl IntoRawFd for IpcBytesReceiver {
+        fn into_raw_fd(self) -> RawFd {
+            self.os_receiver.into_raw_fd()
+        }
+    }
+
+    impl FromRawFd for IpcBytesReceiver {
+        unsafe fn from_raw_fd(fd: RawFd) -> IpcBytesReceiver {
+            IpcBytesReceiver {
+                os_receiver: OsIpcReceiver::from_raw_fd(fd),
+            }
+        }
+    }
+
+    impl AsRawFd for IpcBytesSender {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_sender.as_raw_fd()
+        }
+    }
+
+    impl IntoRawFd for IpcBytesSender {
+        /// Like with `IpcSender`, this may return a duplicate.
+        fn into_raw_fd(self) -> RawFd {
+            self.os_sender.into_raw_fd()
+        }
+    }
+
+    impl FromRawFd for IpcBytesSender {
+        unsafe fn from_raw_fd(fd: RawFd) -> IpcBytesSender {
+            IpcBytesSender {
+                os_sender: OsIpcSender::from_raw_fd(fd),
+            }
+        }
+    }
+
+    impl AsRawFd for OpaqueIpcReceiver {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_receiver.as_raw_fd()
+        }
+    }
+
+    impl IntoRawFd for OpaqueIpcReceiver {
+        fn into_raw_fd(self) -> RawFd {
+            self.os_receiver.into_raw_fd()
+        }
+    }
+
+    impl FromRawFd for OpaqueIpcReceiver {
+        unsafe fn from_raw_fd(fd: RawFd) -> OpaqueIpcReceiver {
+            OpaqueIpcReceiver {
+                os_receiver: OsIpcReceiver::from_raw_fd(fd),
+            }
+        }
+    }
+
+    impl AsRawFd for OpaqueIpcSender {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_sender.as_raw_fd()
+        }
+    }
+
+    impl IntoRawFd for OpaqueIpcSender {
+        /// Like with `IpcSender`, this may return a duplicate.
+        fn into_raw_fd(self) -> RawFd {
+            self.os_sender.into_raw_fd()
+        }
+    }
+
+    impl FromRawFd for OpaqueIpcSender {
+        unsafe fn from_raw_fd(fd: RawFd) -> OpaqueIpcSender {
+            OpaqueIpcSender {
+                os_sender: OsIpcSender::from_raw_fd(fd),
+            }
+        }
+    }
+
+    impl AsRawFd for IpcSharedMemory {
+        fn as_raw_fd(&self) -> RawFd {
+            self.os_shared_memory.as_raw_fd()
+        }
+    }
+
+    impl IntoRawFd for IpcSharedMemory {
+        fn into_raw_fd(self) -> RawFd {
+            self.os_shared_memory.into_raw_fd()
+        }
+    }
+
+    impl FromRawFd for IpcSharedMemory {
+        unsafe fn from_raw_fd(fd: RawFd) -> IpcSharedMemory {
+            IpcSharedMemory {
+                os_shared_memory: OsIpcSharedMemory::from_raw_fd(fd),
+            }
+        }
+    }
+}
+
+/// Error returned by the blocking `recv()` operations,
+/// modelled after `std::sync::mpsc::RecvError`.
+#[derive(Debug)]
+pub enum RecvError {
+    /// All senders for the channel have been dropped, and no messages are left.
+    Disconnected,
+    /// The underlying OS receive operation failed.
+    Io(Error),
+    /// A message was received, but could not be deserialized.
+    Deserialize(bincode::Error),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RecvError::Disconnected => formatter.write_str(error::Error::description(self)),
+            RecvError::Io(ref err) => write!(formatter, "receive failed: {}", err),
+            RecvError::Deserialize(ref err) => write!(formatter, "deserialization failed: {}", err),
+        }
+    }
+}
+
+impl error::Error for RecvError {
+    fn description(&self) -> &str {
+        match *self {
+            RecvError::Disconnected => "receiving on a closed channel",
+            RecvError::Io(ref err) => error::Error::description(err),
+            RecvError::Deserialize(ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RecvError::Disconnected => None,
+            RecvError::Io(ref err) => Some(err),
+            RecvError::Deserialize(ref err) => Some(err),
+        }
+    }
+}
+
+/// Error returned by the non-blocking `try_recv()` operations,
+/// modelled after `std::sync::mpsc::TryRecvError`.
+#[derive(Debug)]
+pub enum TryRecvError {
+    /// No message is currently pending, but the channel is still open.
+    Empty,
+    /// All senders for the channel have been dropped, and no messages are left.
+    Disconnected,
+    /// The underlying OS receive operation failed.
+    Io(Error),
+    /// A message was received, but could not be deserialized.
+    Deserialize(bincode::Error),
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            TryRecvError::Empty |
+            TryRecvError::Disconnected => formatter.write_str(error::Error::description(self)),
+            TryRecvError::Io(ref err) => write!(formatter, "receive failed: {}", err),
+            TryRecvError::Deserialize(ref err) => {
+                write!(formatter, "deserialization failed: {}", err)
+            }
+        }
+    }
+}
+
+impl error::Error for TryRecvError {
+    fn description(&self) -> &str {
+        match *self {
+            TryRecvError::Empty => "receiving on an empty channel",
+            TryRecvError::Disconnected => "receiving on a closed channel",
+            TryRecvError::Io(ref err) => error::Error::description(err),
+            TryRecvError::Deserialize(ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            TryRecvError::Empty | TryRecvError::Disconnected => None,
+            TryRecvError::Io(ref err) => Some(err),
+            TryRecvError::Deserialize(ref err) => Some(err),
+        }
+    }
+}
+
+/// Error returned by the `recv_timeout()` and `recv_deadline()` operations,
+/// modelled after `std::sync::mpsc::RecvTimeoutError`.
+#[derive(Debug)]
+pub enum RecvTimeoutError {
+    /// No message arrived before the timeout expired, but the channel is still open.
+    Timeout,
+    /// All senders for the channel have been dropped, and no messages are left.
+    Disconnected,
+    /// The underlying OS receive operation failed.
+    Io(Error),
+    /// A message was received, but could not be deserialized.
+    Deserialize(bincode::Error),
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            RecvTimeoutError::Timeout |
+            RecvTimeoutError::Disconnected => formatter.write_str(error::Error::description(self)),
+            RecvTimeoutError::Io(ref err) => write!(formatter, "receive failed: {}", err),
+            RecvTimeoutError::Deserialize(ref err) => {
+                write!(formatter, "deserialization failed: {}", err)
+            }
+        }
+    }
+}
+
+impl error::Error for RecvTimeoutError {
+    fn description(&self) -> &str {
+        match *self {
+            RecvTimeoutError::Timeout => "timed out waiting on channel",
+            RecvTimeoutError::Disconnected => "receiving on a closed channel",
+            RecvTimeoutError::Io(ref err) => error::Error::description(err),
+            RecvTimeoutError::Deserialize(ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected => None,
+            RecvTimeoutError::Io(ref err) => Some(err),
+            RecvTimeoutError::Deserialize(ref err) => Some(err),
+        }
+    }
+}
+
+/// Why a `send()` operation failed.
+#[derive(Debug)]
+pub enum SendErrorKind {
+    /// The receiver for the channel has been dropped.
+    Disconnected,
+    /// The underlying OS send operation failed.
+    Io(Error),
+    /// The message could not be serialized.
+    Serialize(bincode::Error),
+}
+
+impl fmt::Display for SendErrorKind {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            SendErrorKind::Disconnected => formatter.write_str(error::Error::description(self)),
+            SendErrorKind::Io(ref err) => write!(formatter, "send failed: {}", err),
+            SendErrorKind::Serialize(ref err) => write!(formatter, "serialization failed: {}", err),
+        }
+    }
+}
+
+impl error::Error for SendErrorKind {
+    fn description(&self) -> &str {
+        match *self {
+            SendErrorKind::Disconnected => "sending on a closed channel",
+            SendErrorKind::Io(ref err) => error::Error::description(err),
+            SendErrorKind::Serialize(ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SendErrorKind::Disconnected => None,
+            SendErrorKind::Io(ref err) => Some(err),
+            SendErrorKind::Serialize(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<bincode::Error> for SendErrorKind {
+    fn from(err: bincode::Error) -> SendErrorKind {
+        SendErrorKind::Serialize(err)
+    }
+}
+
+/// Error returned by `IpcSender::send()`, handing back the value that could not be sent,
+/// like `std::sync::mpsc::SendError`.
+///
+/// Receivers in the value are taken out of it for sending, so they are lost regardless.
+pub struct SendError<T> {
+    value: T,
+    kind: SendErrorKind,
+}
+
+impl<T> SendError<T> {
+    fn new(value: T, kind: SendErrorKind) -> SendError<T> {
+        SendError {
+            value: value,
+            kind: kind,
+        }
+    }
+
+    pub fn kind(&self) -> &SendErrorKind {
+        &self.kind
+    }
+
+    /// Takes back the value that could not be sent.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    pub fn into_parts(self) -> (T, SendErrorKind) {
+        (self.value, self.kind)
+    }
+}
+
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        formatter.debug_struct("SendError").field("kind", &self.kind).finish()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt::Display::fmt(&self.kind, formatter)
+    }
+}
+
+impl<T> error::Error for SendError<T> where T: Send {
+    fn description(&self) -> &str {
+        error::Error::description(&self.kind)
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        error::Error::cause(&self.kind)
+    }
+}
+
+/// Error returned by the non-blocking `try_send()` operations,
+/// modelled after `std::sync::mpsc::TrySendError`.
+#[derive(Debug)]
+pub enum TrySendError {
+    /// The channel is full, so sending would block.
+    Full,
+    /// The receiver for the channel has been dropped.
+    Disconnected,
+    /// The underlying OS send operation failed.
+    Io(Error),
+    /// The message could not be serialized.
+    Serialize(bincode::Error),
+}
+
+impl fmt::Display for TrySendError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            TrySendError::Full |
+            TrySendError::Disconnected => formatter.write_str(error::Error::description(self)),
+            TrySendError::Io(ref err) => write!(formatter, "send failed: {}", err),
+            TrySendError::Serialize(ref err) => write!(formatter, "serialization failed: {}", err),
+        }
+    }
+}
+
+impl error::Error for TrySendError {
+    fn description(&self) -> &str {
+        match *self {
+            TrySendError::Full => "sending on a full channel",
+            TrySendError::Disconnected => "sending on a closed channel",
+            TrySendError::Io(ref err) => error::Error::description(err),
+            TrySendError::Serialize(ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            TrySendError::Full | TrySendError::Disconnected => None,
+            TrySendError::Io(ref err) => Some(err),
+            TrySendError::Serialize(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<SendErrorKind> for TrySendError {
+    fn from(err: SendErrorKind) -> TrySendError {
+        match err {
+            SendErrorKind::Disconnected => TrySendError::Disconnected,
+            SendErrorKind::Io(err) => TrySendError::Io(err),
+            SendErrorKind::Serialize(err) => TrySendError::Serialize(err),
+        }
+    }
+}
+
+/// Error returned by the `send_timeout()` operations,
+/// modelled after `std::sync::mpsc::SendTimeoutError`.
+#[derive(Debug)]
+pub enum SendTimeoutError {
+    /// The message couldn't be sent before the timeout expired.
+    Timeout,
+    /// The receiver for the channel has been dropped.
+    Disconnected,
+    /// The underlying OS send operation failed.
+    Io(Error),
+    /// The message could not be serialized.
+    Serialize(bincode::Error),
+}
+
+impl fmt::Display for SendTimeoutError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            SendTimeoutError::Timeout |
+            SendTimeoutError::Disconnected => formatter.write_str(error::Error::description(self)),
+            SendTimeoutError::Io(ref err) => write!(formatter, "send failed: {}", err),
+            SendTimeoutError::Serialize(ref err) => {
+                write!(formatter, "serialization failed: {}", err)
+            }
+        }
+    }
+}
+
+impl error::Error for SendTimeoutError {
+    fn description(&self) -> &str {
+        match *self {
+            SendTimeoutError::Timeout => "timed out waiting on channel",
+            SendTimeoutError::Disconnected => "sending on a closed channel",
+            SendTimeoutError::Io(ref err) => error::Error::description(err),
+            SendTimeoutError::Serialize(ref err) => error::Error::description(&**err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SendTimeoutError::Timeout | SendTimeoutError::Disconnected => None,
+            SendTimeoutError::Io(ref err) => Some(err),
+            SendTimeoutError::Serialize(ref err) => Some(err),
+        }
+    }
+}
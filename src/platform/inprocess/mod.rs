@@ -8,6 +8,9 @@
 // except according to those terms.
 
 use bincode;
+use ipc;
+#[cfg(unix)]
+use libc;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::collections::hash_map::HashMap;
@@ -15,9 +18,11 @@ use std::cell::{RefCell};
 use std::io::{Error, ErrorKind};
 use std::slice;
 use std::fmt::{self, Debug, Formatter};
-use std::cmp::{PartialEq};
+use std::cmp::{self, PartialEq};
 use std::ops::{Deref, RangeFrom};
 use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::usize;
 use uuid::Uuid;
 
@@ -49,6 +54,9 @@ impl ServerRecord {
 
 lazy_static! {
     static ref ONE_SHOT_SERVERS: Mutex<HashMap<String,ServerRecord>> = Mutex::new(HashMap::new());
+    /// Where to hand the receiving ends of new connections to `OsIpcServer`s.
+    static ref SERVERS: Mutex<HashMap<String,mpsc::Sender<OsIpcReceiver>>> =
+        Mutex::new(HashMap::new());
 }
 
 struct MpscChannelMessage(Vec<u8>, Vec<OsIpcChannel>, Vec<OsIpcSharedMemory>);
@@ -82,6 +90,28 @@ impl OsIpcReceiver {
         OsIpcReceiver::new(receiver.unwrap())
     }
 
+    /// Not supported: an MPSC receiver can't be shared.
+    pub fn try_clone(&self) -> Result<OsIpcReceiver,MpscError> {
+        Err(MpscError::UnsupportedError)
+    }
+
+    /// The credentials of the other end, which is always this very process.
+    #[cfg(unix)]
+    pub fn peer_credentials(&self) -> Result<ipc::PeerCredentials,MpscError> {
+        unsafe {
+            Ok(ipc::PeerCredentials {
+                pid: Some(libc::getpid() as u32),
+                uid: libc::geteuid(),
+                gid: libc::getegid(),
+            })
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn peer_credentials(&self) -> Result<ipc::PeerCredentials,MpscError> {
+        Err(MpscError::UnsupportedError)
+    }
+
     pub fn recv(&self) -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),MpscError> {
         let r = self.receiver.borrow();
         match r.as_ref().unwrap().recv() {
@@ -99,9 +129,27 @@ impl OsIpcReceiver {
                                                  c.into_iter().map(OsOpaqueIpcChannel::new).collect(),
                                                  s)),
             Err(mpsc::TryRecvError::Disconnected) => Err(MpscError::ChannelClosedError),
-            Err(_) => Err(MpscError::UnknownError),
+            Err(mpsc::TryRecvError::Empty) => Err(MpscError::ChannelEmptyError),
         }
     }
+
+    pub fn recv_timeout(&self, duration: Duration)
+                        -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),MpscError> {
+        let r = self.receiver.borrow();
+        match r.as_ref().unwrap().recv_timeout(duration) {
+            Ok(MpscChannelMessage(d,c,s)) => Ok((d,
+                                                 c.into_iter().map(OsOpaqueIpcChannel::new).collect(),
+                                                 s)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(MpscError::ChannelClosedError),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(MpscError::TimedOutError),
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant)
+                         -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),MpscError> {
+        let now = Instant::now();
+        self.recv_timeout(if deadline > now { deadline - now } else { Duration::new(0, 0) })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -124,7 +172,17 @@ impl OsIpcSender {
     }
 
     pub fn connect(name: String) -> Result<OsIpcSender,MpscError> {
-        let record = ONE_SHOT_SERVERS.lock().unwrap().get(&name).unwrap().clone();
+        let server = SERVERS.lock().unwrap().get(&name).cloned();
+        if let Some(connection_sender) = server {
+            // Every client gets a channel of its own.
+            let (sender, receiver) = try!(channel());
+            try!(connection_sender.send(receiver).map_err(|_| MpscError::ChannelClosedError));
+            return Ok(sender)
+        }
+        let record = match ONE_SHOT_SERVERS.lock().unwrap().get(&name) {
+            Some(record) => record.clone(),
+            None => return Err(MpscError::UnknownServerError),
+        };
         record.connect();
         Ok(record.sender)
     }
@@ -144,6 +202,27 @@ impl OsIpcSender {
             Ok(_) => Ok(()),
         }
     }
+
+    /// The underlying MPSC channel is unbounded, so this never blocks anyway.
+    pub fn try_send(&self,
+                    data: &[u8],
+                    ports: Vec<OsIpcChannel>,
+                    shared_memory_regions: Vec<OsIpcSharedMemory>)
+                    -> Result<(),MpscError>
+    {
+        self.send(data, ports, shared_memory_regions)
+    }
+
+    /// The underlying MPSC channel is unbounded, so this never blocks anyway.
+    pub fn send_timeout(&self,
+                        data: &[u8],
+                        ports: Vec<OsIpcChannel>,
+                        shared_memory_regions: Vec<OsIpcSharedMemory>,
+                        _duration: Duration)
+                        -> Result<(),MpscError>
+    {
+        self.send(data, ports, shared_memory_regions)
+    }
 }
 
 pub struct OsIpcReceiverSet {
@@ -168,6 +247,22 @@ impl OsIpcReceiverSet {
         Ok(last_index)
     }
 
+    /// Stop listening on the receiver with the given `id`, handing it back to the caller.
+    pub fn remove(&mut self, id: u64) -> Option<OsIpcReceiver> {
+        self.receiver_ids.iter().position(|&r_id| r_id == id).map(|index| {
+            self.receiver_ids.remove(index);
+            self.receivers.remove(index)
+        })
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.receiver_ids.contains(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.receivers.len()
+    }
+
     pub fn select(&mut self) -> Result<Vec<OsIpcSelectionResult>,MpscError> {
         let mut receivers: Vec<Option<mpsc::Receiver<MpscChannelMessage>>> = Vec::with_capacity(self.receivers.len());
         let mut r_id: Option<u64> = None;
@@ -223,6 +318,48 @@ impl OsIpcReceiverSet {
             }
         }
     }
+
+    /// Like `select()`, but returns an empty result instead of blocking.
+    pub fn try_select(&mut self) -> Result<Vec<OsIpcSelectionResult>,MpscError> {
+        let mut selection_results = Vec::new();
+        let mut index = 0;
+        while index < self.receivers.len() {
+            match self.receivers[index].try_recv() {
+                Ok((data, channels, shmems)) => {
+                    selection_results.push(OsIpcSelectionResult::DataReceived(
+                            self.receiver_ids[index], data, channels, shmems));
+                    index += 1;
+                }
+                Err(MpscError::ChannelEmptyError) => index += 1,
+                Err(MpscError::ChannelClosedError) => {
+                    self.receivers.remove(index);
+                    let r_id = self.receiver_ids.remove(index);
+                    selection_results.push(OsIpcSelectionResult::ChannelClosed(r_id));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(selection_results)
+    }
+
+    /// Like `select()`, but returns an empty result if nothing arrived within `duration`.
+    ///
+    /// `mpsc::Select` doesn't support timeouts, so this polls with `try_select()` instead.
+    pub fn select_timeout(&mut self, duration: Duration)
+                          -> Result<Vec<OsIpcSelectionResult>,MpscError> {
+        let deadline = match Instant::now().checked_add(duration) {
+            Some(deadline) => deadline,
+            None => return self.select(),
+        };
+        loop {
+            let selection_results = try!(self.try_select());
+            let now = Instant::now();
+            if !selection_results.is_empty() || now >= deadline {
+                return Ok(selection_results)
+            }
+            thread::sleep(cmp::min(deadline - now, Duration::from_millis(1)));
+        }
+    }
 }
 
 pub enum OsIpcSelectionResult {
@@ -249,6 +386,11 @@ pub struct OsIpcOneShotServer {
 }
 
 impl OsIpcOneShotServer {
+    pub fn with_options(_: &ipc::IpcServerOptions)
+                        -> Result<(OsIpcOneShotServer, String),MpscError> {
+        OsIpcOneShotServer::new()
+    }
+
     pub fn new() -> Result<(OsIpcOneShotServer, String),MpscError> {
         let (sender, receiver) = try!(channel());
 
@@ -266,14 +408,62 @@ impl OsIpcOneShotServer {
                                     Vec<OsOpaqueIpcChannel>,
                                     Vec<OsIpcSharedMemory>),MpscError>
     {
+        self.accept_filtered(|_| true)
+    }
+
+    /// Like `accept()`, but skipping clients for which `allows` returns `false`,
+    /// along with the first message they send, and waiting for another one instead.
+    pub fn accept_filtered<F>(self, allows: F)
+                              -> Result<(OsIpcReceiver,
+                                         Vec<u8>,
+                                         Vec<OsOpaqueIpcChannel>,
+                                         Vec<OsIpcSharedMemory>),MpscError>
+                              where F: Fn(&OsIpcReceiver) -> bool {
         let record = ONE_SHOT_SERVERS.lock().unwrap().get(&self.name).unwrap().clone();
         record.accept();
+        while !allows(&self.receiver) {
+            try!(self.receiver.recv());
+            record.accept();
+        }
         ONE_SHOT_SERVERS.lock().unwrap().remove(&self.name).unwrap();
         let (data, channels, shmems) = try!(self.receiver.recv());
         Ok((self.receiver, data, channels, shmems))
     }
 }
 
+/// A server that accepts any number of clients, unlike `OsIpcOneShotServer`.
+pub struct OsIpcServer {
+    connection_receiver: mpsc::Receiver<OsIpcReceiver>,
+    name: String,
+}
+
+impl Drop for OsIpcServer {
+    fn drop(&mut self) {
+        SERVERS.lock().unwrap().remove(&self.name);
+    }
+}
+
+impl OsIpcServer {
+    pub fn with_options(_: &ipc::IpcServerOptions) -> Result<(OsIpcServer, String),MpscError> {
+        OsIpcServer::new()
+    }
+
+    pub fn new() -> Result<(OsIpcServer, String),MpscError> {
+        let (connection_sender, connection_receiver) = mpsc::channel();
+        let name = Uuid::new_v4().to_string();
+        SERVERS.lock().unwrap().insert(name.clone(), connection_sender);
+        Ok((OsIpcServer {
+            connection_receiver: connection_receiver,
+            name: name.clone(),
+        }, name))
+    }
+
+    /// Waits for the next client to connect, without waiting for it to send anything.
+    pub fn accept(&self) -> Result<OsIpcReceiver,MpscError> {
+        self.connection_receiver.recv().map_err(|_| MpscError::ChannelClosedError)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum OsIpcChannel {
     Sender(OsIpcSender),
@@ -305,6 +495,43 @@ impl OsOpaqueIpcChannel {
             OsIpcChannel::Receiver(_) => panic!("Opaque channel is not a sender!"),
         }
     }
+
+    /// Whether the channel has already been converted into a sender or receiver.
+    pub fn is_consumed(&self) -> bool {
+        self.channel.borrow().is_none()
+    }
+
+    /// Like `to_receiver()`, but returns `None` if the channel has already been consumed,
+    /// or is not a receiver.
+    pub fn try_to_receiver(&self) -> Option<OsIpcReceiver> {
+        let mut channel = self.channel.borrow_mut();
+        match channel.take() {
+            Some(OsIpcChannel::Receiver(r)) => Some(r),
+            other => {
+                *channel = other;
+                None
+            }
+        }
+    }
+
+    /// Like `to_sender()`, but returns `None` if the channel has already been consumed,
+    /// or is not a sender.
+    pub fn try_to_sender(&mut self) -> Option<OsIpcSender> {
+        let mut channel = self.channel.borrow_mut();
+        match channel.take() {
+            Some(OsIpcChannel::Sender(s)) => Some(s),
+            other => {
+                *channel = other;
+                None
+            }
+        }
+    }
+
+    /// Takes the channel as it is, e.g. to pass it on to another process.
+    /// Returns `None` if the channel has already been consumed.
+    pub fn to_channel(&mut self) -> Option<OsIpcChannel> {
+        self.channel.borrow_mut().take()
+    }
 }
 
 pub struct OsIpcSharedMemory {
@@ -375,7 +602,11 @@ impl OsIpcSharedMemory {
 #[derive(Debug, PartialEq)]
 pub enum MpscError {
     ChannelClosedError,
+    ChannelEmptyError,
     BrokenPipeError,
+    TimedOutError,
+    UnsupportedError,
+    UnknownServerError,
     UnknownError,
 }
 
@@ -384,6 +615,11 @@ impl MpscError {
     pub fn channel_is_closed(&self) -> bool {
         *self == MpscError::ChannelClosedError
     }
+
+    /// Whether connecting failed because there's no server by that name (yet).
+    pub fn is_not_listening(&self) -> bool {
+        *self == MpscError::UnknownServerError
+    }
 }
 
 impl From<MpscError> for bincode::Error {
@@ -398,11 +634,78 @@ impl From<MpscError> for Error {
             MpscError::ChannelClosedError => {
                 Error::new(ErrorKind::ConnectionReset, "MPSC channel sender closed")
             }
+            MpscError::ChannelEmptyError => {
+                Error::new(ErrorKind::WouldBlock, "MPSC channel has no pending messages")
+            }
             MpscError::BrokenPipeError => {
                 Error::new(ErrorKind::BrokenPipe, "MPSC channel receiver closed")
             }
+            MpscError::TimedOutError => {
+                Error::new(ErrorKind::TimedOut, "MPSC channel receive timed out")
+            }
+            MpscError::UnsupportedError => {
+                Error::new(ErrorKind::Other, "Not supported by MPSC channels on this platform")
+            }
+            MpscError::UnknownServerError => {
+                Error::new(ErrorKind::NotFound, "No MPSC channel server with that name")
+            }
             MpscError::UnknownError => Error::new(ErrorKind::Other, "Other MPSC channel error"),
         }
     }
 }
 
+impl From<MpscError> for ipc::RecvError {
+    fn from(mpsc_error: MpscError) -> ipc::RecvError {
+        match mpsc_error {
+            MpscError::ChannelClosedError => ipc::RecvError::Disconnected,
+            _ => ipc::RecvError::Io(mpsc_error.into()),
+        }
+    }
+}
+
+impl From<MpscError> for ipc::TryRecvError {
+    fn from(mpsc_error: MpscError) -> ipc::TryRecvError {
+        match mpsc_error {
+            MpscError::ChannelClosedError => ipc::TryRecvError::Disconnected,
+            MpscError::ChannelEmptyError => ipc::TryRecvError::Empty,
+            _ => ipc::TryRecvError::Io(mpsc_error.into()),
+        }
+    }
+}
+
+impl From<MpscError> for ipc::RecvTimeoutError {
+    fn from(mpsc_error: MpscError) -> ipc::RecvTimeoutError {
+        match mpsc_error {
+            MpscError::ChannelClosedError => ipc::RecvTimeoutError::Disconnected,
+            MpscError::TimedOutError => ipc::RecvTimeoutError::Timeout,
+            _ => ipc::RecvTimeoutError::Io(mpsc_error.into()),
+        }
+    }
+}
+
+impl From<MpscError> for ipc::SendErrorKind {
+    fn from(mpsc_error: MpscError) -> ipc::SendErrorKind {
+        match mpsc_error {
+            MpscError::BrokenPipeError => ipc::SendErrorKind::Disconnected,
+            _ => ipc::SendErrorKind::Io(mpsc_error.into()),
+        }
+    }
+}
+
+impl From<MpscError> for ipc::TrySendError {
+    fn from(mpsc_error: MpscError) -> ipc::TrySendError {
+        match mpsc_error {
+            MpscError::BrokenPipeError => ipc::TrySendError::Disconnected,
+            _ => ipc::TrySendError::Io(mpsc_error.into()),
+        }
+    }
+}
+
+impl From<MpscError> for ipc::SendTimeoutError {
+    fn from(mpsc_error: MpscError) -> ipc::SendTimeoutError {
+        match mpsc_error {
+            MpscError::BrokenPipeError => ipc::SendTimeoutError::Disconnected,
+            _ => ipc::SendTimeoutError::Io(mpsc_error.into()),
+        }
+    }
+}
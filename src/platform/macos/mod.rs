@@ -10,9 +10,11 @@
 use self::mach_sys::{kern_return_t, mach_msg_body_t, mach_msg_header_t};
 use self::mach_sys::{mach_msg_ool_descriptor_t, mach_msg_port_descriptor_t};
 use self::mach_sys::{mach_msg_timeout_t, mach_port_limits_t, mach_port_msgcount_t};
-use self::mach_sys::{mach_port_right_t, mach_port_t, mach_task_self_, vm_inherit_t};
+use self::mach_sys::{mach_port_right_t, mach_port_t, mach_port_type_t, mach_task_self_};
+use self::mach_sys::vm_inherit_t;
 
 use bincode;
+use ipc;
 use libc::{self, c_char, c_uint, c_void, size_t};
 use rand::{self, Rng};
 use std::cell::Cell;
@@ -24,6 +26,7 @@ use std::mem;
 use std::ops::Deref;
 use std::ptr;
 use std::slice;
+use std::time::{Duration, Instant};
 use std::usize;
 
 mod mach_sys;
@@ -37,7 +40,10 @@ static BOOTSTRAP_PREFIX: &'static str = "org.rust-lang.ipc-channel.";
 
 const BOOTSTRAP_NAME_IN_USE: kern_return_t = 1101;
 const BOOTSTRAP_SUCCESS: kern_return_t = 0;
+const BOOTSTRAP_UNKNOWN_SERVICE: kern_return_t = 1102;
+const KERN_INVALID_ARGUMENT: kern_return_t = 4;
 const KERN_INVALID_RIGHT: kern_return_t = 17;
+const KERN_NOT_SUPPORTED: kern_return_t = 46;
 const KERN_SUCCESS: kern_return_t = 0;
 const MACH_MSGH_BITS_COMPLEX: u32 = 0x80000000;
 const MACH_MSG_IPC_KERNEL: kern_return_t = 0x00000800;
@@ -64,6 +70,8 @@ const MACH_PORT_QLIMIT_MAX: mach_port_msgcount_t = MACH_PORT_QLIMIT_LARGE;
 const MACH_PORT_RIGHT_PORT_SET: mach_port_right_t = 3;
 const MACH_PORT_RIGHT_RECEIVE: mach_port_right_t = 1;
 const MACH_PORT_RIGHT_SEND: mach_port_right_t = 0;
+const MACH_PORT_TYPE_RECEIVE: mach_port_type_t = 0x20000;
+const MACH_PORT_TYPE_SEND: mach_port_type_t = 0x10000;
 const MACH_RCV_BODY_ERROR: kern_return_t = 0x1000400c;
 const MACH_RCV_HEADER_ERROR: kern_return_t = 0x1000400b;
 const MACH_RCV_INTERRUPTED: kern_return_t = 0x10004005;
@@ -100,6 +108,7 @@ const MACH_SEND_MSG: i32 = 1;
 const MACH_SEND_MSG_TOO_SMALL: kern_return_t = 0x10000008;
 const MACH_SEND_NO_BUFFER: kern_return_t = 0x1000000d;
 const MACH_SEND_TIMED_OUT: kern_return_t = 0x10000004;
+const MACH_SEND_TIMEOUT: i32 = 0x10;
 const MACH_SEND_TOO_LARGE: kern_return_t = 0x1000000e;
 const TASK_BOOTSTRAP_PORT: i32 = 4;
 const VM_INHERIT_SHARE: vm_inherit_t = 0;
@@ -176,6 +185,17 @@ impl OsIpcReceiver {
         OsIpcReceiver::from_name(self.consume_port())
     }
 
+    /// Not supported: there is only ever one receive right for a port.
+    pub fn try_clone(&self) -> Result<OsIpcReceiver,MachError> {
+        Err(MachError::Unknown(KERN_NOT_SUPPORTED))
+    }
+
+    /// Not supported: anyone holding a send right can send to a port,
+    /// so there is no single peer.
+    pub fn peer_credentials(&self) -> Result<ipc::PeerCredentials,MachError> {
+        Err(MachError::Unknown(KERN_NOT_SUPPORTED))
+    }
+
     fn sender(&self) -> Result<OsIpcSender,MachError> {
         let port = self.port.get();
         debug_assert!(port != MACH_PORT_NULL);
@@ -302,6 +322,19 @@ impl OsIpcReceiver {
                     -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),MachError> {
         self.recv_with_blocking_mode(BlockingMode::Nonblocking)
     }
+
+    pub fn recv_timeout(&self, duration: Duration)
+                        -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),MachError> {
+        match Instant::now().checked_add(duration) {
+            Some(deadline) => self.recv_deadline(deadline),
+            None => self.recv(),
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant)
+                         -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),MachError> {
+        self.recv_with_blocking_mode(BlockingMode::Deadline(deadline))
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -364,7 +397,10 @@ impl OsIpcSender {
             }
 
             let mut port = 0;
-            let c_name = CString::new(name).unwrap();
+            let c_name = match CString::new(name) {
+                Ok(c_name) => c_name,
+                Err(_) => return Err(MachError::Unknown(KERN_INVALID_ARGUMENT)),
+            };
             let os_result = bootstrap_look_up(bootstrap_port, c_name.as_ptr(), &mut port);
             if os_result == BOOTSTRAP_SUCCESS {
                 Ok(OsIpcSender::from_name(port))
@@ -383,6 +419,38 @@ impl OsIpcSender {
                 ports: Vec<OsIpcChannel>,
                 shared_memory_regions: Vec<OsIpcSharedMemory>)
                 -> Result<(),MachError> {
+        self.send_with_mode(data, ports, shared_memory_regions, BlockingMode::Blocking)
+    }
+
+    /// Like `send()`, but fails with `SendTimedOut` instead of blocking.
+    pub fn try_send(&self,
+                    data: &[u8],
+                    ports: Vec<OsIpcChannel>,
+                    shared_memory_regions: Vec<OsIpcSharedMemory>)
+                    -> Result<(),MachError> {
+        self.send_with_mode(data, ports, shared_memory_regions, BlockingMode::Nonblocking)
+    }
+
+    /// Like `send()`, but fails with `SendTimedOut` if it would block for longer than `duration`.
+    pub fn send_timeout(&self,
+                        data: &[u8],
+                        ports: Vec<OsIpcChannel>,
+                        shared_memory_regions: Vec<OsIpcSharedMemory>,
+                        duration: Duration)
+                        -> Result<(),MachError> {
+        let blocking_mode = match Instant::now().checked_add(duration) {
+            Some(deadline) => BlockingMode::Deadline(deadline),
+            None => BlockingMode::Blocking,
+        };
+        self.send_with_mode(data, ports, shared_memory_regions, blocking_mode)
+    }
+
+    fn send_with_mode(&self,
+                      data: &[u8],
+                      ports: Vec<OsIpcChannel>,
+                      shared_memory_regions: Vec<OsIpcSharedMemory>,
+                      blocking_mode: BlockingMode)
+                      -> Result<(),MachError> {
         unsafe {
             let size = Message::size_of(data.len(), ports.len(), shared_memory_regions.len());
             let message = libc::malloc(size as size_t) as *mut Message;
@@ -435,12 +503,19 @@ impl OsIpcSender {
             data_dest = data_dest.offset(mem::size_of::<usize>() as isize);
             ptr::copy_nonoverlapping(data.as_ptr(), data_dest, data_size);
 
+            let (flags, timeout) = match blocking_mode {
+                BlockingMode::Blocking => (MACH_SEND_MSG, MACH_MSG_TIMEOUT_NONE),
+                BlockingMode::Nonblocking => (MACH_SEND_MSG | MACH_SEND_TIMEOUT, 0),
+                BlockingMode::Deadline(deadline) => {
+                    (MACH_SEND_MSG | MACH_SEND_TIMEOUT, mach_timeout_until(deadline))
+                }
+            };
             let os_result = mach_sys::mach_msg(message as *mut _,
-                                               MACH_SEND_MSG,
+                                               flags,
                                                (*message).header.msgh_size,
                                                0,
                                                MACH_PORT_NULL,
-                                               MACH_MSG_TIMEOUT_NONE,
+                                               timeout,
                                                MACH_PORT_NULL);
             libc::free(message as *mut _);
             if os_result != MACH_MSG_SUCCESS {
@@ -473,7 +548,14 @@ pub struct OsOpaqueIpcChannel {
 impl Drop for OsOpaqueIpcChannel {
     fn drop(&mut self) {
         // Make sure we don't leak!
-        debug_assert!(self.port == MACH_PORT_NULL);
+        //
+        // If the receiving side didn't claim the port (e.g. because deserialization failed),
+        // release the right we still hold on it.
+        if self.has_right(MACH_PORT_TYPE_RECEIVE) {
+            drop(self.to_receiver())
+        } else if self.port != MACH_PORT_NULL {
+            drop(self.to_sender())
+        }
     }
 }
 
@@ -494,10 +576,60 @@ impl OsOpaqueIpcChannel {
     pub fn to_receiver(&mut self) -> OsIpcReceiver {
         OsIpcReceiver::from_name(mem::replace(&mut self.port, MACH_PORT_NULL))
     }
+
+    /// Whether the channel has already been converted into a sender or receiver.
+    pub fn is_consumed(&self) -> bool {
+        self.port == MACH_PORT_NULL
+    }
+
+    /// Like `to_sender()`, but returns `None` if the channel has already been consumed,
+    /// or doesn't hold a send right.
+    pub fn try_to_sender(&mut self) -> Option<OsIpcSender> {
+        if self.has_right(MACH_PORT_TYPE_SEND) {
+            Some(self.to_sender())
+        } else {
+            None
+        }
+    }
+
+    /// Like `to_receiver()`, but returns `None` if the channel has already been consumed,
+    /// or doesn't hold a receive right.
+    pub fn try_to_receiver(&mut self) -> Option<OsIpcReceiver> {
+        if self.has_right(MACH_PORT_TYPE_RECEIVE) {
+            Some(self.to_receiver())
+        } else {
+            None
+        }
+    }
+
+    /// Takes the channel as it is, e.g. to pass it on to another process.
+    /// Returns `None` if the channel has already been consumed.
+    pub fn to_channel(&mut self) -> Option<OsIpcChannel> {
+        if self.port == MACH_PORT_NULL {
+            None
+        } else if self.has_right(MACH_PORT_TYPE_RECEIVE) {
+            Some(OsIpcChannel::Receiver(self.to_receiver()))
+        } else {
+            Some(OsIpcChannel::Sender(self.to_sender()))
+        }
+    }
+
+    fn has_right(&self, right: mach_port_type_t) -> bool {
+        if self.port == MACH_PORT_NULL {
+            return false
+        }
+        let mut port_type: mach_port_type_t = 0;
+        let os_result = unsafe {
+            mach_sys::mach_port_type(mach_task_self(), self.port, &mut port_type)
+        };
+        os_result == KERN_SUCCESS && (port_type & right) != 0
+    }
 }
 
 pub struct OsIpcReceiverSet {
     port: Cell<mach_port_t>,
+    /// The receive rights currently moved into the port set.
+    members: Vec<mach_port_t>,
 }
 
 impl OsIpcReceiverSet {
@@ -509,6 +641,7 @@ impl OsIpcReceiverSet {
         if os_result == KERN_SUCCESS {
             Ok(OsIpcReceiverSet {
                 port: Cell::new(port),
+                members: vec![],
             })
         } else {
             Err(MachError::from(os_result))
@@ -521,14 +654,69 @@ impl OsIpcReceiverSet {
             mach_sys::mach_port_move_member(mach_task_self(), receiver_port, self.port.get())
         };
         if os_result == KERN_SUCCESS {
+            self.members.push(receiver_port);
             Ok(receiver_port as u64)
         } else {
             Err(MachError::from(os_result))
         }
     }
 
+    /// Stop listening on the receiver with the given `id`, handing it back to the caller.
+    pub fn remove(&mut self, id: u64) -> Option<OsIpcReceiver> {
+        let index = match self.members.iter().position(|&port| port as u64 == id) {
+            Some(index) => index,
+            None => return None,
+        };
+        let receiver_port = self.members[index];
+        let os_result = unsafe {
+            mach_sys::mach_port_move_member(mach_task_self(), receiver_port, MACH_PORT_NULL)
+        };
+        if os_result != KERN_SUCCESS {
+            return None
+        }
+        self.members.remove(index);
+        Some(OsIpcReceiver::from_name(receiver_port))
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.members.iter().any(|&port| port as u64 == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
     pub fn select(&mut self) -> Result<Vec<OsIpcSelectionResult>,MachError> {
-        select(self.port.get(), BlockingMode::Blocking).map(|result| vec![result])
+        self.select_with_mode(BlockingMode::Blocking)
+    }
+
+    /// Like `select()`, but returns an empty result instead of blocking.
+    pub fn try_select(&mut self) -> Result<Vec<OsIpcSelectionResult>,MachError> {
+        self.select_with_mode(BlockingMode::Nonblocking)
+    }
+
+    /// Like `select()`, but returns an empty result if nothing arrived within `duration`.
+    pub fn select_timeout(&mut self, duration: Duration)
+                          -> Result<Vec<OsIpcSelectionResult>,MachError> {
+        match Instant::now().checked_add(duration) {
+            Some(deadline) => self.select_with_mode(BlockingMode::Deadline(deadline)),
+            None => self.select(),
+        }
+    }
+
+    fn select_with_mode(&mut self, blocking_mode: BlockingMode)
+                        -> Result<Vec<OsIpcSelectionResult>,MachError> {
+        match select(self.port.get(), blocking_mode) {
+            Ok(OsIpcSelectionResult::ChannelClosed(id)) => {
+                // Release the receive right (which also takes it out of the port set),
+                // like the other platforms close the receiver on disconnect.
+                drop(self.remove(id));
+                Ok(vec![OsIpcSelectionResult::ChannelClosed(id)])
+            }
+            Ok(result) => Ok(vec![result]),
+            Err(MachError::RcvTimedOut) => Ok(vec![]),
+            Err(err) => Err(err),
+        }
     }
 }
 
@@ -554,6 +742,23 @@ impl OsIpcSelectionResult {
 enum BlockingMode {
     Blocking,
     Nonblocking,
+    Deadline(Instant),
+}
+
+/// Convert the time left until `deadline` to a `mach_msg()` timeout in milliseconds.
+///
+/// Rounds up, so we never give up before the deadline; and clamps overly long timeouts.
+fn mach_timeout_until(deadline: Instant) -> mach_msg_timeout_t {
+    let now = Instant::now();
+    let timeout = if deadline > now { deadline - now } else { Duration::new(0, 0) };
+    let millis = timeout.as_secs()
+                        .saturating_mul(1000)
+                        .saturating_add((timeout.subsec_nanos() as u64 + 999_999) / 1_000_000);
+    if millis > mach_msg_timeout_t::max_value() as u64 {
+        mach_msg_timeout_t::max_value()
+    } else {
+        millis as mach_msg_timeout_t
+    }
 }
 
 fn select(port: mach_port_t, blocking_mode: BlockingMode)
@@ -567,6 +772,9 @@ fn select(port: mach_port_t, blocking_mode: BlockingMode)
         let (flags, timeout) = match blocking_mode {
             BlockingMode::Blocking => (MACH_RCV_MSG | MACH_RCV_LARGE, MACH_MSG_TIMEOUT_NONE),
             BlockingMode::Nonblocking => (MACH_RCV_MSG | MACH_RCV_LARGE | MACH_RCV_TIMEOUT, 0),
+            BlockingMode::Deadline(deadline) => {
+                (MACH_RCV_MSG | MACH_RCV_LARGE | MACH_RCV_TIMEOUT, mach_timeout_until(deadline))
+            }
         };
         match mach_sys::mach_msg(message as *mut _,
                                  flags,
@@ -667,6 +875,11 @@ impl Drop for OsIpcOneShotServer {
 }
 
 impl OsIpcOneShotServer {
+    pub fn with_options(_: &ipc::IpcServerOptions)
+                        -> Result<(OsIpcOneShotServer, String),MachError> {
+        OsIpcOneShotServer::new()
+    }
+
     pub fn new() -> Result<(OsIpcOneShotServer, String),MachError> {
         let receiver = try!(OsIpcReceiver::new());
         let name = try!(receiver.register_bootstrap_name());
@@ -680,6 +893,20 @@ impl OsIpcOneShotServer {
                                    Vec<u8>,
                                    Vec<OsOpaqueIpcChannel>,
                                    Vec<OsIpcSharedMemory>),MachError> {
+        self.accept_filtered(|_| true)
+    }
+
+    /// Like `accept()`, but only if `allows` returns `true`. Clients' credentials
+    /// aren't available here, so this fails right away otherwise.
+    pub fn accept_filtered<F>(self, allows: F)
+                              -> Result<(OsIpcReceiver,
+                                         Vec<u8>,
+                                         Vec<OsOpaqueIpcChannel>,
+                                         Vec<OsIpcSharedMemory>),MachError>
+                              where F: Fn(&OsIpcReceiver) -> bool {
+        if !allows(&self.receiver) {
+            return Err(MachError::Unknown(KERN_NOT_SUPPORTED))
+        }
         let (bytes, channels, shared_memory_regions) = try!(self.receiver.recv());
         Ok((self.receiver.consume(), bytes, channels, shared_memory_regions))
     }
@@ -871,6 +1098,11 @@ impl MachError {
     pub fn channel_is_closed(&self) -> bool {
         *self == MachError::NotifyNoSenders
     }
+
+    /// Whether connecting failed because the name isn't registered (yet).
+    pub fn is_not_listening(&self) -> bool {
+        *self == MachError::Unknown(BOOTSTRAP_UNKNOWN_SERVICE)
+    }
 }
 
 impl From<MachError> for bincode::Error {
@@ -879,6 +1111,64 @@ impl From<MachError> for bincode::Error {
     }
 }
 
+impl From<MachError> for ipc::RecvError {
+    fn from(mach_error: MachError) -> ipc::RecvError {
+        match mach_error {
+            MachError::NotifyNoSenders => ipc::RecvError::Disconnected,
+            _ => ipc::RecvError::Io(mach_error.into()),
+        }
+    }
+}
+
+impl From<MachError> for ipc::TryRecvError {
+    fn from(mach_error: MachError) -> ipc::TryRecvError {
+        match mach_error {
+            MachError::NotifyNoSenders => ipc::TryRecvError::Disconnected,
+            MachError::RcvTimedOut => ipc::TryRecvError::Empty,
+            _ => ipc::TryRecvError::Io(mach_error.into()),
+        }
+    }
+}
+
+impl From<MachError> for ipc::RecvTimeoutError {
+    fn from(mach_error: MachError) -> ipc::RecvTimeoutError {
+        match mach_error {
+            MachError::NotifyNoSenders => ipc::RecvTimeoutError::Disconnected,
+            MachError::RcvTimedOut => ipc::RecvTimeoutError::Timeout,
+            _ => ipc::RecvTimeoutError::Io(mach_error.into()),
+        }
+    }
+}
+
+impl From<MachError> for ipc::SendErrorKind {
+    fn from(mach_error: MachError) -> ipc::SendErrorKind {
+        match mach_error {
+            MachError::SendInvalidDest => ipc::SendErrorKind::Disconnected,
+            _ => ipc::SendErrorKind::Io(mach_error.into()),
+        }
+    }
+}
+
+impl From<MachError> for ipc::TrySendError {
+    fn from(mach_error: MachError) -> ipc::TrySendError {
+        match mach_error {
+            MachError::SendInvalidDest => ipc::TrySendError::Disconnected,
+            MachError::SendTimedOut => ipc::TrySendError::Full,
+            _ => ipc::TrySendError::Io(mach_error.into()),
+        }
+    }
+}
+
+impl From<MachError> for ipc::SendTimeoutError {
+    fn from(mach_error: MachError) -> ipc::SendTimeoutError {
+        match mach_error {
+            MachError::SendInvalidDest => ipc::SendTimeoutError::Disconnected,
+            MachError::SendTimedOut => ipc::SendTimeoutError::Timeout,
+            _ => ipc::SendTimeoutError::Io(mach_error.into()),
+        }
+    }
+}
+
 impl From<kern_return_t> for MachError {
     fn from(code: kern_return_t) -> MachError {
         match code {
@@ -33,6 +33,11 @@ mod os {
 pub use self::os::{OsIpcChannel, OsIpcOneShotServer, OsIpcReceiver, OsIpcReceiverSet};
 pub use self::os::{OsIpcSelectionResult, OsIpcSender, OsIpcSharedMemory};
 pub use self::os::{OsOpaqueIpcChannel, channel};
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+pub use self::os::OsIpcServer;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux",
+                                                target_os = "freebsd")))]
+pub use self::os::{bootstrap, spawn};
 
 #[cfg(test)]
 mod test;
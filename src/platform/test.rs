@@ -10,11 +10,14 @@
 use platform::{self, OsIpcChannel, OsIpcReceiverSet};
 use platform::{OsIpcSharedMemory};
 use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::thread;
 
 use platform::{OsIpcSender, OsIpcOneShotServer};
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+use platform::OsIpcServer;
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
 use libc::{kill, SIGSTOP, SIGCONT};
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
@@ -643,6 +646,28 @@ fn server_connect_first() {
                (data, vec![], vec![]));
 }
 
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+#[test]
+fn server_multiple_clients() {
+    let (server, name) = OsIpcServer::new().unwrap();
+    let data: &[u8] = b"1234567";
+
+    for _ in 0..2 {
+        let name = name.clone();
+        thread::spawn(move || {
+            let tx = OsIpcSender::connect(name).unwrap();
+            tx.send(data, vec![], vec![]).unwrap();
+        });
+    }
+
+    for _ in 0..2 {
+        let (received_data, received_channels, received_shared_memory_regions) =
+            server.accept().unwrap().recv().unwrap();
+        assert_eq!((&received_data[..], received_channels, received_shared_memory_regions),
+                   (data, vec![], vec![]));
+    }
+}
+
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
 #[test]
 fn cross_process() {
@@ -761,6 +786,52 @@ fn no_senders_notification_try_recv() {
     assert!(result.unwrap_err().channel_is_closed());
 }
 
+#[test]
+fn recv_timeout() {
+    let (tx, rx) = platform::channel().unwrap();
+    let start = Instant::now();
+    let result = rx.recv_timeout(Duration::from_millis(50));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    let error = result.unwrap_err();
+    assert!(!error.channel_is_closed());
+    assert_eq!(Error::from(error).kind(), ErrorKind::TimedOut);
+
+    let data: &[u8] = b"1234567";
+    tx.send(data, Vec::new(), Vec::new()).unwrap();
+    let (received_data, received_channels, received_shared_memory) =
+        rx.recv_timeout(Duration::from_millis(50)).unwrap();
+    assert_eq!((&received_data[..], received_channels, received_shared_memory),
+               (data, Vec::new(), Vec::new()));
+}
+
+#[test]
+fn recv_deadline_delayed() {
+    let (tx, rx) = platform::channel().unwrap();
+    let data: &[u8] = b"1234567";
+    let thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(30));
+        tx.send(data, Vec::new(), Vec::new()).unwrap();
+    });
+    let (received_data, received_channels, received_shared_memory) =
+        rx.recv_deadline(Instant::now() + Duration::from_secs(10)).unwrap();
+    assert_eq!((&received_data[..], received_channels, received_shared_memory),
+               (data, Vec::new(), Vec::new()));
+    thread.join().unwrap();
+}
+
+/// Checks that a channel closed notification is returned by `recv_timeout()`,
+/// rather than waiting for the timeout to expire.
+#[test]
+fn no_senders_notification_recv_timeout() {
+    let (sender, receiver) = platform::channel().unwrap();
+    drop(sender);
+    let start = Instant::now();
+    let result = receiver.recv_timeout(Duration::from_secs(10));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(result.is_err());
+    assert!(result.unwrap_err().channel_is_closed());
+}
+
 #[test]
 fn try_recv_large() {
     let (tx, rx) = platform::channel().unwrap();
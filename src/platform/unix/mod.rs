@@ -9,25 +9,30 @@
 
 use bincode;
 use fnv::FnvHasher;
+use ipc;
 use libc::{self, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE, SOCK_SEQPACKET, SOL_SOCKET};
-use libc::{SO_LINGER, S_IFMT, S_IFSOCK, c_char, c_int, c_void, getsockopt};
+use libc::{SO_LINGER, S_IFMT, S_IFSOCK, c_char, c_int, c_short, c_void, getsockopt};
 use libc::{iovec, mode_t, msghdr, off_t, recvmsg, sendmsg};
 use libc::{setsockopt, size_t, sockaddr, sockaddr_un, socketpair, socklen_t, sa_family_t};
 use std::cell::Cell;
 use std::cmp;
 use std::collections::HashMap;
-use std::ffi::{CStr, CString};
+use std::env;
+use std::ffi::CString;
 use std::fmt::{self, Debug, Formatter};
 use std::hash::BuildHasherDefault;
 use std::io::{Error, ErrorKind};
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::{Deref, RangeFrom};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
 use std::ptr;
 use std::slice;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
-use std::time::UNIX_EPOCH;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use std::thread;
 use mio::unix::EventedFd;
 use mio::{Poll, Token, Events, Ready, PollOpt};
@@ -51,12 +56,28 @@ type IovLen = i32;
 #[cfg(target_os = "freebsd")]
 type MsgControlLen = socklen_t;
 
-unsafe fn new_sockaddr_un(path: *const c_char) -> (sockaddr_un, usize) {
+/// Names starting with a NUL are in the Linux abstract namespace; anything else is a path.
+unsafe fn new_sockaddr_un(name: &[u8]) -> Result<(sockaddr_un, usize),UnixError> {
     let mut sockaddr: sockaddr_un = mem::zeroed();
-    libc::strncpy(sockaddr.sun_path.as_mut_ptr(),
-                  path, sockaddr.sun_path.len() - 1);
+    let is_abstract = name.first() == Some(&0);
+    if !is_abstract && name.contains(&0) {
+        return Err(UnixError(libc::EINVAL))
+    }
+    // Paths need room for the terminating NUL.
+    if name.len() + if is_abstract { 0 } else { 1 } > sockaddr.sun_path.len() {
+        return Err(UnixError(libc::ENAMETOOLONG))
+    }
+    for (dest, &byte) in sockaddr.sun_path.iter_mut().zip(name) {
+        *dest = byte as c_char;
+    }
     sockaddr.sun_family = libc::AF_UNIX as sa_family_t;
-    (sockaddr, mem::size_of::<sockaddr_un>())
+    let len = if is_abstract {
+        // Every byte of an abstract name counts, so don't pass the trailing zeroes.
+        mem::size_of::<sockaddr_un>() - sockaddr.sun_path.len() + name.len()
+    } else {
+        mem::size_of::<sockaddr_un>()
+    };
+    Ok((sockaddr, len))
 }
 
 lazy_static! {
@@ -74,10 +95,18 @@ lazy_static! {
 // A global count used to create unique IDs
 static SHM_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
 
+// A global count used to create unique names in the abstract socket namespace
+static SOCKET_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
 pub fn channel() -> Result<(OsIpcSender, OsIpcReceiver),UnixError> {
+    new_channel(0)
+}
+
+/// Like `channel()`, with extra `flags` for the socket type, such as `SOCK_CLOEXEC`.
+fn new_channel(flags: c_int) -> Result<(OsIpcSender, OsIpcReceiver),UnixError> {
     let mut results = [0, 0];
     unsafe {
-        if socketpair(libc::AF_UNIX, SOCK_SEQPACKET, 0, &mut results[0]) >= 0 {
+        if socketpair(libc::AF_UNIX, SOCK_SEQPACKET | flags, 0, &mut results[0]) >= 0 {
             Ok((OsIpcSender::from_fd(results[0]), OsIpcReceiver::from_fd(results[1])))
         } else {
             Err(UnixError::last())
@@ -85,6 +114,76 @@ pub fn channel() -> Result<(OsIpcSender, OsIpcReceiver),UnixError> {
     }
 }
 
+/// The environment variable `spawn()` passes the child's ends of the channels in,
+/// as `<receiver fd>,<sender fd>`.
+const BOOTSTRAP_ENV_VAR: &'static str = "IPC_CHANNEL_BOOTSTRAP_FD";
+
+/// Spawns `command` with a channel to it and one back, for it to pick up with `bootstrap()`.
+pub fn spawn(command: &mut Command) -> Result<(Child, OsIpcSender, OsIpcReceiver),Error> {
+    // All ends start out close-on-exec, so that no child inherits our ends,
+    // or its own ends would never get disconnected. That includes children
+    // spawned by other threads in the meantime.
+    let (parent_sender, child_receiver) = try!(new_channel(libc::SOCK_CLOEXEC));
+    let (child_sender, parent_receiver) = try!(new_channel(libc::SOCK_CLOEXEC));
+    let child_fds = [child_receiver.fd.get(), child_sender.fd.0];
+
+    // Only the child gets to keep its ends. `command` holds on to the hook,
+    // so disarm it for any later spawns.
+    let armed = Arc::new(AtomicBool::new(true));
+    let child_armed = armed.clone();
+    command.before_exec(move || {
+        if child_armed.load(Ordering::SeqCst) {
+            for &fd in &child_fds {
+                try!(set_cloexec(fd, false));
+            }
+        }
+        Ok(())
+    });
+    command.env(BOOTSTRAP_ENV_VAR, format!("{},{}", child_fds[0], child_fds[1]));
+    let child = command.spawn();
+    armed.store(false, Ordering::SeqCst);
+    command.env_remove(BOOTSTRAP_ENV_VAR);
+    Ok((try!(child), parent_sender, parent_receiver))
+}
+
+/// Takes the channels passed by `spawn()`, in the child process.
+pub fn bootstrap() -> Result<(OsIpcReceiver, OsIpcSender),UnixError> {
+    let fds = match env::var(BOOTSTRAP_ENV_VAR) {
+        Ok(fds) => fds,
+        Err(_) => return Err(UnixError(libc::ENOENT)),
+    };
+    // Make sure nobody else takes ownership of the same file descriptors.
+    env::remove_var(BOOTSTRAP_ENV_VAR);
+
+    let fds: Vec<c_int> = fds.split(',').filter_map(|fd| fd.parse().ok()).collect();
+    if fds.len() != 2 || fds[0] == fds[1] || !fds.iter().all(|&fd| is_socket(fd)) {
+        return Err(UnixError(libc::EBADF))
+    }
+    // Don't pass them on to any children of our own.
+    for &fd in &fds {
+        try!(set_cloexec(fd, true));
+    }
+    Ok((OsIpcReceiver::from_fd(fds[0]), OsIpcSender::from_fd(fds[1])))
+}
+
+fn set_cloexec(fd: c_int, cloexec: bool) -> Result<(),UnixError> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 {
+            return Err(UnixError::last())
+        }
+        let flags = if cloexec {
+            flags | libc::FD_CLOEXEC
+        } else {
+            flags & !libc::FD_CLOEXEC
+        };
+        if libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+            return Err(UnixError::last())
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone, Copy)]
 struct PollEntry {
     pub id: u64,
@@ -124,6 +223,23 @@ impl OsIpcReceiver {
         OsIpcReceiver::from_fd(self.consume_fd())
     }
 
+    /// Duplicates the socket; each message then goes to whichever receiver asks first.
+    pub fn try_clone(&self) -> Result<OsIpcReceiver,UnixError> {
+        let fd = unsafe { libc::dup(self.fd.get()) };
+        if fd < 0 {
+            return Err(UnixError::last())
+        }
+        Ok(OsIpcReceiver::from_fd(fd))
+    }
+
+    /// The credentials of the process at the other end, as of when the connection was made.
+    ///
+    /// For channels created with `channel()`, that's the process which created the channel,
+    /// wherever the sender went since.
+    pub fn peer_credentials(&self) -> Result<ipc::PeerCredentials,UnixError> {
+        peer_credentials(self.fd.get())
+    }
+
     pub fn recv(&self)
                 -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),UnixError> {
         recv(self.fd.get(), BlockingMode::Blocking)
@@ -133,6 +249,19 @@ impl OsIpcReceiver {
                     -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),UnixError> {
         recv(self.fd.get(), BlockingMode::Nonblocking)
     }
+
+    pub fn recv_timeout(&self, duration: Duration)
+                        -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),UnixError> {
+        match Instant::now().checked_add(duration) {
+            Some(deadline) => self.recv_deadline(deadline),
+            None => self.recv(),
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant)
+                         -> Result<(Vec<u8>, Vec<OsOpaqueIpcChannel>, Vec<OsIpcSharedMemory>),UnixError> {
+        recv(self.fd.get(), BlockingMode::Deadline(deadline))
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -221,6 +350,44 @@ impl OsIpcSender {
                 channels: Vec<OsIpcChannel>,
                 shared_memory_regions: Vec<OsIpcSharedMemory>)
                 -> Result<(),UnixError> {
+        self.send_with_mode(data, channels, shared_memory_regions, BlockingMode::Blocking)
+    }
+
+    /// Like `send()`, but fails with `EAGAIN` instead of blocking.
+    ///
+    /// For fragmented messages, this only applies to the first fragment:
+    /// either nothing is sent at all, or the whole message is --
+    /// in which case sending the remaining fragments might still block.
+    pub fn try_send(&self,
+                    data: &[u8],
+                    channels: Vec<OsIpcChannel>,
+                    shared_memory_regions: Vec<OsIpcSharedMemory>)
+                    -> Result<(),UnixError> {
+        self.send_with_mode(data, channels, shared_memory_regions, BlockingMode::Nonblocking)
+    }
+
+    /// Like `send()`, but fails with `ETIMEDOUT` if it would block for longer than `duration`.
+    ///
+    /// The same caveat regarding fragmented messages applies as for `try_send()`.
+    pub fn send_timeout(&self,
+                        data: &[u8],
+                        channels: Vec<OsIpcChannel>,
+                        shared_memory_regions: Vec<OsIpcSharedMemory>,
+                        duration: Duration)
+                        -> Result<(),UnixError> {
+        let blocking_mode = match Instant::now().checked_add(duration) {
+            Some(deadline) => BlockingMode::Deadline(deadline),
+            None => BlockingMode::Blocking,
+        };
+        self.send_with_mode(data, channels, shared_memory_regions, blocking_mode)
+    }
+
+    fn send_with_mode(&self,
+                      data: &[u8],
+                      channels: Vec<OsIpcChannel>,
+                      shared_memory_regions: Vec<OsIpcSharedMemory>,
+                      blocking_mode: BlockingMode)
+                      -> Result<(),UnixError> {
 
         let mut fds = Vec::new();
         for channel in channels.iter() {
@@ -236,8 +403,40 @@ impl OsIpcSender {
         // Not to be confused with the length of the data to send in this packet
         // (i.e. the length of the data buffer passed in),
         // which in a fragmented send will be smaller than the total message length.
-        fn send_first_fragment(sender_fd: c_int, fds: &[c_int], data_buffer: &[u8], len: usize)
+        //
+        // The `blocking_mode` only applies to this first fragment:
+        // once it is sent, the message is committed, and the rest has to follow in any case.
+        fn send_first_fragment(sender_fd: c_int,
+                               fds: &[c_int],
+                               data_buffer: &[u8],
+                               len: usize,
+                               blocking_mode: BlockingMode)
                                -> Result<(),UnixError> {
+            let flags = match blocking_mode {
+                BlockingMode::Blocking => 0,
+                BlockingMode::Nonblocking | BlockingMode::Deadline(_) => libc::MSG_DONTWAIT,
+            };
+            loop {
+                if let BlockingMode::Deadline(deadline) = blocking_mode {
+                    try!(poll_until(sender_fd, libc::POLLOUT, deadline));
+                }
+                let result = send_first_fragment_with_flags(sender_fd, fds, data_buffer, len, flags);
+                match (result, blocking_mode) {
+                    // The socket might not have room for the whole packet yet,
+                    // even though `poll()` reported it as writable: just wait some more.
+                    (Err(UnixError(errno)), BlockingMode::Deadline(_))
+                        if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK => {}
+                    (result, _) => return result,
+                }
+            }
+        }
+
+        fn send_first_fragment_with_flags(sender_fd: c_int,
+                                          fds: &[c_int],
+                                          data_buffer: &[u8],
+                                          len: usize,
+                                          flags: c_int)
+                                          -> Result<(),UnixError> {
             let result = unsafe {
                 let cmsg_length = mem::size_of_val(fds);
                 let (cmsg_buffer, cmsg_space) = if cmsg_length > 0 {
@@ -280,7 +479,7 @@ impl OsIpcSender {
                     msg_flags: 0,
                 };
 
-                let result = sendmsg(sender_fd, &msghdr, 0);
+                let result = sendmsg(sender_fd, &msghdr, flags);
                 libc::free(cmsg_buffer as *mut c_void);
                 result
             };
@@ -332,7 +531,7 @@ impl OsIpcSender {
 
         // If the message is small enough, try sending it in a single fragment.
         if data.len() <= Self::get_max_fragment_size() {
-            match send_first_fragment(self.fd.0, &fds[..], data, data.len()) {
+            match send_first_fragment(self.fd.0, &fds[..], data, data.len(), blocking_mode) {
                 Ok(_) => return Ok(()),
                 Err(error) => {
                     // ENOBUFS means the kernel failed to allocate a buffer large enough
@@ -371,7 +570,11 @@ impl OsIpcSender {
 
                 // This fragment always uses the full allowable buffer size.
                 end_byte_position = Self::first_fragment_size(sendbuf_size);
-                send_first_fragment(self.fd.0, &fds[..], &data[..end_byte_position], data.len())
+                send_first_fragment(self.fd.0,
+                                    &fds[..],
+                                    &data[..end_byte_position],
+                                    data.len(),
+                                    blocking_mode)
             } else {
                 // Followup fragment. No header; but offset by amount of data already sent.
 
@@ -398,13 +601,17 @@ impl OsIpcSender {
     }
 
     pub fn connect(name: String) -> Result<OsIpcSender,UnixError> {
-        let name = CString::new(name).unwrap();
         unsafe {
+            let (sockaddr, len) = try!(new_sockaddr_un(name.as_bytes()));
             let fd = libc::socket(libc::AF_UNIX, SOCK_SEQPACKET, 0);
-            let (sockaddr, len) = new_sockaddr_un(name.as_ptr());
-            if libc::connect(fd, &sockaddr as *const _ as *const sockaddr, len as socklen_t) < 0 {
+            if fd < 0 {
                 return Err(UnixError::last())
             }
+            if libc::connect(fd, &sockaddr as *const _ as *const sockaddr, len as socklen_t) < 0 {
+                let errno = UnixError::last();
+                libc::close(fd);
+                return Err(errno)
+            }
 
             Ok(OsIpcSender::from_fd(fd))
         }
@@ -472,11 +679,61 @@ impl OsIpcReceiverSet {
         Ok(last_index)
     }
 
+    /// Stop listening on the receiver with the given `id`, handing it back to the caller.
+    ///
+    /// If it can't be deregistered, it stays in the set, and this returns `None`.
+    pub fn remove(&mut self, id: u64) -> Option<OsIpcReceiver> {
+        let (fd_token, fd) = match self.pollfds.iter().find(|&(_, entry)| entry.id == id) {
+            Some((&fd_token, entry)) => (fd_token, entry.fd),
+            None => return None,
+        };
+        if self.poll.deregister(&EventedFd(&fd)).is_err() {
+            return None
+        }
+        self.pollfds.remove(&fd_token);
+        Some(OsIpcReceiver::from_fd(fd))
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.pollfds.values().any(|poll_entry| poll_entry.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pollfds.len()
+    }
+
     pub fn select(&mut self) -> Result<Vec<OsIpcSelectionResult>,UnixError> {
+        self.select_with_mode(BlockingMode::Blocking)
+    }
+
+    /// Like `select()`, but returns an empty result instead of blocking.
+    pub fn try_select(&mut self) -> Result<Vec<OsIpcSelectionResult>,UnixError> {
+        self.select_with_mode(BlockingMode::Nonblocking)
+    }
+
+    /// Like `select()`, but returns an empty result if nothing arrived within `duration`.
+    pub fn select_timeout(&mut self, duration: Duration)
+                          -> Result<Vec<OsIpcSelectionResult>,UnixError> {
+        match Instant::now().checked_add(duration) {
+            Some(deadline) => self.select_with_mode(BlockingMode::Deadline(deadline)),
+            None => self.select(),
+        }
+    }
+
+    fn select_with_mode(&mut self, blocking_mode: BlockingMode)
+                        -> Result<Vec<OsIpcSelectionResult>,UnixError> {
         let mut selection_results = Vec::new();
         let mut num_events = 0;
         while num_events == 0 {
-            match self.poll.poll(&mut self.events, None) {
+            let timeout = match blocking_mode {
+                BlockingMode::Blocking => None,
+                BlockingMode::Nonblocking => Some(Duration::new(0, 0)),
+                BlockingMode::Deadline(deadline) => {
+                    let now = Instant::now();
+                    Some(if deadline > now { deadline - now } else { Duration::new(0, 0) })
+                }
+            };
+            match self.poll.poll(&mut self.events, timeout) {
                 Ok(sz) => {
                     num_events = sz;
                 },
@@ -486,6 +743,17 @@ impl OsIpcReceiverSet {
                     }
                 }
             }
+            if num_events == 0 {
+                match blocking_mode {
+                    BlockingMode::Blocking => {}
+                    BlockingMode::Nonblocking => return Ok(selection_results),
+                    BlockingMode::Deadline(deadline) => {
+                        if Instant::now() >= deadline {
+                            return Ok(selection_results)
+                        }
+                    }
+                }
+            }
         }
 
         for evt in self.events.iter() {
@@ -552,10 +820,15 @@ impl Drop for OsOpaqueIpcChannel {
     fn drop(&mut self) {
         // Make sure we don't leak!
         //
-        // The `OsOpaqueIpcChannel` objects should always be used,
-        // i.e. converted with `to_sender()` or `to_receiver()` --
-        // so the value should already be unset before the object gets dropped.
-        debug_assert!(self.fd == -1);
+        // The `OsOpaqueIpcChannel` objects are normally converted with `to_sender()` or
+        // `to_receiver()` before being dropped. If the receiving side didn't claim the channel
+        // (e.g. because deserialization failed), we still own the descriptor, and close it here.
+        if self.fd != -1 {
+            unsafe {
+                let result = libc::close(self.fd);
+                assert!(thread::panicking() || result == 0);
+            }
+        }
     }
 }
 
@@ -573,10 +846,46 @@ impl OsOpaqueIpcChannel {
     pub fn to_receiver(&mut self) -> OsIpcReceiver {
         OsIpcReceiver::from_fd(mem::replace(&mut self.fd, -1))
     }
+
+    /// Whether the channel has already been converted into a sender or receiver.
+    pub fn is_consumed(&self) -> bool {
+        self.fd == -1
+    }
+
+    /// Like `to_sender()`, but returns `None` if the channel has already been consumed.
+    ///
+    /// Both ends of a channel are plain sockets, so unlike on other platforms,
+    /// a receiver can't be told apart from a sender here.
+    pub fn try_to_sender(&mut self) -> Option<OsIpcSender> {
+        if self.fd == -1 {
+            None
+        } else {
+            Some(self.to_sender())
+        }
+    }
+
+    /// Like `to_receiver()`, but returns `None` if the channel has already been consumed.
+    ///
+    /// See `try_to_sender()` regarding the kind of the channel.
+    pub fn try_to_receiver(&mut self) -> Option<OsIpcReceiver> {
+        if self.fd == -1 {
+            None
+        } else {
+            Some(self.to_receiver())
+        }
+    }
+
+    /// Takes the channel as it is, e.g. to pass it on to another process.
+    /// Returns `None` if the channel has already been consumed.
+    pub fn to_channel(&mut self) -> Option<OsIpcChannel> {
+        // Either end is sent the same way, so it doesn't matter what we call it.
+        self.try_to_sender().map(OsIpcChannel::Sender)
+    }
 }
 
 pub struct OsIpcOneShotServer {
     fd: c_int,
+    name: Vec<u8>,
 }
 
 impl Drop for OsIpcOneShotServer {
@@ -585,63 +894,229 @@ impl Drop for OsIpcOneShotServer {
             let result = libc::close(self.fd);
             assert!(thread::panicking() || result == 0);
         }
+        unlink_socket(&self.name);
     }
 }
 
 impl OsIpcOneShotServer {
     pub fn new() -> Result<(OsIpcOneShotServer, String),UnixError> {
+        OsIpcOneShotServer::with_options(&Default::default())
+    }
+
+    pub fn with_options(options: &ipc::IpcServerOptions)
+                        -> Result<(OsIpcOneShotServer, String),UnixError> {
+        let (fd, name) = try!(listen_on_new_name(options, 10));
+        Ok((OsIpcOneShotServer {
+            fd: fd,
+            name: name.clone().into_bytes(),
+        }, name))
+    }
+
+    pub fn accept(self) -> Result<(OsIpcReceiver,
+                                   Vec<u8>,
+                                   Vec<OsOpaqueIpcChannel>,
+                                   Vec<OsIpcSharedMemory>),UnixError> {
+        self.accept_filtered(|_| true)
+    }
+
+    /// Like `accept()`, but disconnecting clients for which `allows` returns `false`
+    /// before reading anything, and waiting for another one instead.
+    pub fn accept_filtered<F>(self, allows: F)
+                              -> Result<(OsIpcReceiver,
+                                         Vec<u8>,
+                                         Vec<OsOpaqueIpcChannel>,
+                                         Vec<OsIpcSharedMemory>),UnixError>
+                              where F: Fn(&OsIpcReceiver) -> bool {
+        loop {
+            let receiver = try!(accept_connection(self.fd));
+            if allows(&receiver) {
+                let (data, channels, shared_memory_regions) = try!(receiver.recv());
+                return Ok((receiver, data, channels, shared_memory_regions))
+            }
+        }
+    }
+}
+
+/// A server that accepts any number of clients, unlike `OsIpcOneShotServer`.
+pub struct OsIpcServer {
+    fd: c_int,
+    name: Vec<u8>,
+}
+
+impl Drop for OsIpcServer {
+    fn drop(&mut self) {
         unsafe {
-            let fd = libc::socket(libc::AF_UNIX, SOCK_SEQPACKET, 0);
-            let mut path: Vec<u8>;
-            loop {
-                let path_string = CString::new(&b"/tmp/rust-ipc-socket.XXXXXX"[..]).unwrap();
-                path = path_string.as_bytes_with_nul().iter().cloned().collect();
-                if *mktemp(path.as_mut_ptr() as *mut c_char) == 0 {
-                    return Err(UnixError::last())
-                }
+            let result = libc::close(self.fd);
+            assert!(thread::panicking() || result == 0);
+        }
+        unlink_socket(&self.name);
+    }
+}
 
-                let (sockaddr, len) = new_sockaddr_un(path.as_ptr() as *const c_char);
-                if libc::bind(fd, &sockaddr as *const _ as *const sockaddr, len as socklen_t) == 0 {
-                    break
-                }
+impl OsIpcServer {
+    pub fn new() -> Result<(OsIpcServer, String),UnixError> {
+        OsIpcServer::with_options(&Default::default())
+    }
+
+    pub fn with_options(options: &ipc::IpcServerOptions)
+                        -> Result<(OsIpcServer, String),UnixError> {
+        let (fd, name) = try!(listen_on_new_name(options, libc::SOMAXCONN));
+        Ok((OsIpcServer {
+            fd: fd,
+            name: name.clone().into_bytes(),
+        }, name))
+    }
+
+    /// Waits for the next client to connect, without waiting for it to send anything.
+    pub fn accept(&self) -> Result<OsIpcReceiver,UnixError> {
+        accept_connection(self.fd)
+    }
+}
+
+/// Creates a socket listening on a new name, as configured by `options`.
+/// Returns the socket along with the name.
+fn listen_on_new_name(options: &ipc::IpcServerOptions, backlog: c_int)
+                      -> Result<(c_int, String),UnixError> {
+    if options.abstract_namespace {
+        if !cfg!(target_os = "linux") {
+            return Err(UnixError(libc::ENOTSUP))
+        }
+        // There's no file to put somewhere, or to set the permissions of.
+        if options.directory.is_some() || options.mode.is_some() {
+            return Err(UnixError(libc::EINVAL))
+        }
+    }
 
+    unsafe {
+        let fd = libc::socket(libc::AF_UNIX, SOCK_SEQPACKET, 0);
+        if fd < 0 {
+            return Err(UnixError::last())
+        }
+        match bind_new_name(fd, options).and_then(|name| {
+            if libc::listen(fd, backlog) != 0 {
                 let errno = UnixError::last();
-                if errno.0 != libc::EINVAL {
-                    return Err(errno)
-                }
+                unlink_socket(name.as_bytes());
+                return Err(errno)
             }
+            Ok(name)
+        }) {
+            Ok(name) => Ok((fd, name)),
+            Err(errno) => {
+                libc::close(fd);
+                Err(errno)
+            }
+        }
+    }
+}
 
-            if libc::listen(fd, 10) != 0 {
+/// Binds the socket to a new name. Any permissions are set before the caller starts listening,
+/// so that no client can connect before that.
+unsafe fn bind_new_name(fd: c_int, options: &ipc::IpcServerOptions) -> Result<String,UnixError> {
+    let directory = match options.directory {
+        Some(ref directory) => try!(directory.to_str().ok_or(UnixError(libc::EINVAL))),
+        None => "/tmp",
+    };
+    loop {
+        let name = if options.abstract_namespace {
+            let count = SOCKET_COUNT.fetch_add(1, Ordering::Relaxed);
+            let timestamp = UNIX_EPOCH.elapsed().unwrap();
+            format!("\0rust-ipc-socket.{}.{}.{}.{}",
+                    count, *PID, timestamp.as_secs(), timestamp.subsec_nanos())
+        } else {
+            let template = format!("{}/rust-ipc-socket.XXXXXX", directory);
+            let mut path = try!(CString::new(template).map_err(|_| UnixError(libc::EINVAL)))
+                .into_bytes_with_nul();
+            if *mktemp(path.as_mut_ptr() as *mut c_char) == 0 {
                 return Err(UnixError::last())
             }
+            path.pop();
+            String::from_utf8(path).unwrap()
+        };
+
+        let (sockaddr, len) = try!(new_sockaddr_un(name.as_bytes()));
+        if libc::bind(fd, &sockaddr as *const _ as *const sockaddr, len as socklen_t) != 0 {
+            let errno = UnixError::last();
+            if errno.0 != libc::EINVAL && errno.0 != libc::EADDRINUSE {
+                return Err(errno)
+            }
+            continue
+        }
 
-            Ok((OsIpcOneShotServer {
-                fd: fd,
-            }, String::from_utf8(CStr::from_ptr(path.as_ptr() as
-                                                *const c_char).to_bytes().to_owned()).unwrap()))
+        if let Some(mode) = options.mode {
+            let path = CString::new(name.clone()).unwrap();
+            if libc::chmod(path.as_ptr(), mode as mode_t) != 0 {
+                let errno = UnixError::last();
+                unlink_socket(name.as_bytes());
+                return Err(errno)
+            }
         }
+        return Ok(name)
     }
+}
 
-    pub fn accept(self) -> Result<(OsIpcReceiver,
-                                   Vec<u8>,
-                                   Vec<OsOpaqueIpcChannel>,
-                                   Vec<OsIpcSharedMemory>),UnixError> {
+/// Removes the socket file, if the name is a path.
+fn unlink_socket(name: &[u8]) {
+    if name.first() == Some(&0) {
+        return
+    }
+    if let Ok(path) = CString::new(name) {
         unsafe {
-            let sockaddr: *mut sockaddr = ptr::null_mut();
-            let sockaddr_len: *mut socklen_t = ptr::null_mut();
-            let client_fd = libc::accept(self.fd, sockaddr, sockaddr_len);
-            if client_fd < 0 {
-                return Err(UnixError::last())
-            }
-            try!(make_socket_lingering(client_fd));
+            libc::unlink(path.as_ptr());
+        }
+    }
+}
 
-            let receiver = OsIpcReceiver::from_fd(client_fd);
-            let (data, channels, shared_memory_regions) = try!(receiver.recv());
-            Ok((receiver, data, channels, shared_memory_regions))
+fn accept_connection(fd: c_int) -> Result<OsIpcReceiver,UnixError> {
+    unsafe {
+        let sockaddr: *mut sockaddr = ptr::null_mut();
+        let sockaddr_len: *mut socklen_t = ptr::null_mut();
+        let client_fd = libc::accept(fd, sockaddr, sockaddr_len);
+        if client_fd < 0 {
+            return Err(UnixError::last())
         }
+        try!(make_socket_lingering(client_fd));
+        Ok(OsIpcReceiver::from_fd(client_fd))
     }
 }
 
+#[cfg(target_os = "linux")]
+fn peer_credentials(fd: c_int) -> Result<ipc::PeerCredentials,UnixError> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as socklen_t;
+    let result = unsafe {
+        getsockopt(fd,
+                   SOL_SOCKET,
+                   libc::SO_PEERCRED,
+                   &mut credentials as *mut _ as *mut c_void,
+                   &mut len)
+    };
+    if result < 0 {
+        return Err(UnixError::last())
+    }
+    Ok(ipc::PeerCredentials {
+        pid: Some(credentials.pid as u32),
+        uid: credentials.uid,
+        gid: credentials.gid,
+    })
+}
+
+#[cfg(target_os = "freebsd")]
+fn peer_credentials(fd: c_int) -> Result<ipc::PeerCredentials,UnixError> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } < 0 {
+        return Err(UnixError::last())
+    }
+    Ok(ipc::PeerCredentials {
+        pid: None,
+        uid: uid,
+        gid: gid,
+    })
+}
+
 // Make sure that the kernel doesn't return errors to readers if there's still data left after we
 // close our end.
 //
@@ -811,6 +1286,89 @@ impl OsIpcSharedMemory {
     }
 }
 
+impl AsRawFd for OsIpcReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.get()
+    }
+}
+
+impl IntoRawFd for OsIpcReceiver {
+    fn into_raw_fd(self) -> RawFd {
+        self.consume_fd()
+    }
+}
+
+impl FromRawFd for OsIpcReceiver {
+    unsafe fn from_raw_fd(fd: RawFd) -> OsIpcReceiver {
+        OsIpcReceiver::from_fd(fd)
+    }
+}
+
+impl AsRawFd for OsIpcSender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.0
+    }
+}
+
+impl IntoRawFd for OsIpcSender {
+    /// Clones of a sender share its file descriptor; if there are any left,
+    /// this returns a duplicate of it instead.
+    ///
+    /// # Panics
+    ///
+    /// If the file descriptor needs duplicating and that fails, for example because
+    /// the process is out of file descriptors.
+    fn into_raw_fd(self) -> RawFd {
+        match Arc::try_unwrap(self.fd) {
+            Ok(fd) => {
+                let raw_fd = fd.0;
+                mem::forget(fd);
+                raw_fd
+            }
+            Err(fd) => {
+                let raw_fd = unsafe { libc::dup(fd.0) };
+                assert!(raw_fd >= 0, "failed to duplicate sender: {:?}", UnixError::last());
+                raw_fd
+            }
+        }
+    }
+}
+
+impl FromRawFd for OsIpcSender {
+    unsafe fn from_raw_fd(fd: RawFd) -> OsIpcSender {
+        OsIpcSender::from_fd(fd)
+    }
+}
+
+impl AsRawFd for OsIpcSharedMemory {
+    fn as_raw_fd(&self) -> RawFd {
+        self.store.fd()
+    }
+}
+
+impl IntoRawFd for OsIpcSharedMemory {
+    /// Unmaps the memory, returning the file descriptor behind it.
+    fn into_raw_fd(self) -> RawFd {
+        let raw_fd = self.store.fd();
+        unsafe {
+            if !self.ptr.is_null() {
+                let result = libc::munmap(self.ptr as *mut c_void, self.length);
+                assert!(result == 0);
+            }
+        }
+        // Neither unmap again nor close the file descriptor.
+        mem::forget(self);
+        raw_fd
+    }
+}
+
+impl FromRawFd for OsIpcSharedMemory {
+    /// Maps the whole file, at its current size.
+    unsafe fn from_raw_fd(fd: RawFd) -> OsIpcSharedMemory {
+        OsIpcSharedMemory::from_fd(fd)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UnixError(c_int);
 
@@ -823,6 +1381,11 @@ impl UnixError {
     pub fn channel_is_closed(&self) -> bool {
         self.0 == libc::ECONNRESET
     }
+
+    /// Whether connecting failed because nothing is listening on the name (yet).
+    pub fn is_not_listening(&self) -> bool {
+        self.0 == libc::ENOENT || self.0 == libc::ECONNREFUSED
+    }
 }
 
 impl From<UnixError> for bincode::Error {
@@ -843,10 +1406,74 @@ impl From<Error> for UnixError {
     }
 }
 
+impl From<UnixError> for ipc::RecvError {
+    fn from(unix_error: UnixError) -> ipc::RecvError {
+        if unix_error.channel_is_closed() {
+            ipc::RecvError::Disconnected
+        } else {
+            ipc::RecvError::Io(unix_error.into())
+        }
+    }
+}
+
+impl From<UnixError> for ipc::TryRecvError {
+    fn from(unix_error: UnixError) -> ipc::TryRecvError {
+        match unix_error.0 {
+            libc::ECONNRESET => ipc::TryRecvError::Disconnected,
+            errno if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK => {
+                ipc::TryRecvError::Empty
+            }
+            _ => ipc::TryRecvError::Io(unix_error.into()),
+        }
+    }
+}
+
+impl From<UnixError> for ipc::RecvTimeoutError {
+    fn from(unix_error: UnixError) -> ipc::RecvTimeoutError {
+        match unix_error.0 {
+            libc::ECONNRESET => ipc::RecvTimeoutError::Disconnected,
+            libc::ETIMEDOUT => ipc::RecvTimeoutError::Timeout,
+            _ => ipc::RecvTimeoutError::Io(unix_error.into()),
+        }
+    }
+}
+
+impl From<UnixError> for ipc::SendErrorKind {
+    fn from(unix_error: UnixError) -> ipc::SendErrorKind {
+        match unix_error.0 {
+            libc::EPIPE | libc::ECONNRESET => ipc::SendErrorKind::Disconnected,
+            _ => ipc::SendErrorKind::Io(unix_error.into()),
+        }
+    }
+}
+
+impl From<UnixError> for ipc::TrySendError {
+    fn from(unix_error: UnixError) -> ipc::TrySendError {
+        match unix_error.0 {
+            libc::EPIPE | libc::ECONNRESET => ipc::TrySendError::Disconnected,
+            errno if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK => {
+                ipc::TrySendError::Full
+            }
+            _ => ipc::TrySendError::Io(unix_error.into()),
+        }
+    }
+}
+
+impl From<UnixError> for ipc::SendTimeoutError {
+    fn from(unix_error: UnixError) -> ipc::SendTimeoutError {
+        match unix_error.0 {
+            libc::EPIPE | libc::ECONNRESET => ipc::SendTimeoutError::Disconnected,
+            libc::ETIMEDOUT => ipc::SendTimeoutError::Timeout,
+            _ => ipc::SendTimeoutError::Io(unix_error.into()),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 enum BlockingMode {
     Blocking,
     Nonblocking,
+    Deadline(Instant),
 }
 
 fn recv(fd: c_int, blocking_mode: BlockingMode)
@@ -1005,32 +1632,76 @@ impl UnixCmsg {
 
     unsafe fn recv(&mut self, fd: c_int, blocking_mode: BlockingMode)
                    -> Result<usize, UnixError> {
-        if let BlockingMode::Nonblocking = blocking_mode {
-            if libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) < 0 {
-                return Err(UnixError::last())
+        loop {
+            // Don't toggle `O_NONBLOCK`: it's shared with any duplicates of `fd`,
+            // which may be in a blocking receive right now.
+            let flags = match blocking_mode {
+                BlockingMode::Blocking => 0,
+                BlockingMode::Nonblocking => libc::MSG_DONTWAIT,
+                // A receiver sharing the socket may take the message after `poll()`
+                // said it's there, so `recvmsg()` mustn't block either.
+                BlockingMode::Deadline(deadline) => {
+                    try!(poll_until(fd, libc::POLLIN, deadline));
+                    libc::MSG_DONTWAIT
+                }
+            };
+
+            let result = recvmsg(fd, &mut self.msghdr, flags);
+            if result > 0 {
+                return Ok(result as usize)
+            } else if result == 0 {
+                return Err(UnixError(libc::ECONNRESET))
+            }
+            let error = UnixError::last();
+            match blocking_mode {
+                BlockingMode::Deadline(_)
+                        if error.0 == libc::EAGAIN || error.0 == libc::EWOULDBLOCK => {}
+                _ => return Err(error),
             }
         }
+    }
 
-        let result = recvmsg(fd, &mut self.msghdr, 0);
-        let result = if result > 0 {
-            Ok(result as usize)
+    unsafe fn cmsg_len(&self) -> size_t {
+        (*(self.msghdr.msg_control as *const cmsghdr)).cmsg_len as size_t
+    }
+}
+
+/// Wait until `fd` is ready for any of the given `poll()` events,
+/// giving up with `ETIMEDOUT` once `deadline` has passed.
+fn poll_until(fd: c_int, events: c_short, deadline: Instant) -> Result<(),UnixError> {
+    loop {
+        let now = Instant::now();
+        let timeout = if deadline > now { deadline - now } else { Duration::new(0, 0) };
+        let mut pollfd = libc::pollfd {
+            fd: fd,
+            events: events,
+            revents: 0,
+        };
+        let result = unsafe { libc::poll(&mut pollfd, 1, poll_timeout_millis(timeout)) };
+        if result > 0 {
+            return Ok(())
         } else if result == 0 {
-            Err(UnixError(libc::ECONNRESET))
+            if Instant::now() >= deadline {
+                return Err(UnixError(libc::ETIMEDOUT))
+            }
         } else {
-            Err(UnixError::last())
-        };
-
-        if let BlockingMode::Nonblocking = blocking_mode {
-            if libc::fcntl(fd, libc::F_SETFL, 0) < 0 {
-                return Err(UnixError::last())
+            let error = UnixError::last();
+            if error.0 != libc::EINTR {
+                return Err(error)
             }
         }
-        result
     }
+}
 
-    unsafe fn cmsg_len(&self) -> size_t {
-        (*(self.msghdr.msg_control as *const cmsghdr)).cmsg_len as size_t
-    }
+/// Convert a timeout to the millisecond value `poll()` expects.
+///
+/// Rounds up, so we never wake up before the deadline;
+/// and clamps overly long timeouts, which just makes `poll_until()` go another round.
+fn poll_timeout_millis(timeout: Duration) -> c_int {
+    let millis = timeout.as_secs()
+                        .saturating_mul(1000)
+                        .saturating_add((timeout.subsec_nanos() as u64 + 999_999) / 1_000_000);
+    cmp::min(millis, c_int::max_value() as u64) as c_int
 }
 
 fn is_socket(fd: c_int) -> bool {
@@ -7,43 +7,152 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::collections::HashMap;
-use std::sync::Mutex;
+use bincode;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{self, Receiver, Sender};
-use std::thread;
+use std::thread::{self, JoinHandle};
 
 use ipc::{self, IpcReceiver, IpcReceiverSet, IpcSelectionResult, IpcSender, OpaqueIpcMessage};
-use ipc::{OpaqueIpcReceiver};
+use ipc::{OpaqueIpcReceiver, OpaqueIpcSender};
 use serde::{Deserialize, Serialize};
 
 lazy_static! {
     pub static ref ROUTER: RouterProxy = RouterProxy::new();
 }
 
+/// A handle to a router thread.
+///
+/// Dropping a `RouterProxy` shuts its router down, see `shutdown()`.
 pub struct RouterProxy {
     comm: Mutex<RouterProxyComm>,
+    /// The router thread and the pool threads, if there are any.
+    threads: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl RouterProxy {
     pub fn new() -> RouterProxy {
+        RouterProxy::start(None)
+    }
+
+    /// Creates a router that runs the route handlers on a pool of `threads` threads,
+    /// so a slow handler doesn't hold up the other routes.
+    ///
+    /// The messages of each route are still handled one at a time, in the order they arrived.
+    pub fn with_thread_pool(threads: usize) -> RouterProxy {
+        assert!(threads > 0);
+        RouterProxy::start(Some(threads))
+    }
+
+    fn start(pool_threads: Option<usize>) -> RouterProxy {
         let (msg_sender, msg_receiver) = mpsc::channel();
         let (wakeup_sender, wakeup_receiver) = ipc::channel().unwrap();
-        thread::spawn(move || Router::new(msg_receiver, wakeup_receiver).run());
+        let mut threads = vec![];
+        let mut pool_sender = None;
+        if let Some(pool_threads) = pool_threads {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+            for _ in 0..pool_threads {
+                let receiver = receiver.clone();
+                let msg_sender = msg_sender.clone();
+                let wakeup_sender = wakeup_sender.clone();
+                threads.push(thread::spawn(move || {
+                    run_pool_thread(receiver, msg_sender, wakeup_sender)
+                }));
+            }
+            pool_sender = Some(sender);
+        }
+        threads.push(thread::spawn(move || {
+            Router::new(msg_receiver, wakeup_receiver, pool_sender).run()
+        }));
         RouterProxy {
             comm: Mutex::new(RouterProxyComm {
                 msg_sender: msg_sender,
                 wakeup_sender: wakeup_sender,
+                next_route_id: 0,
             }),
+            threads: Mutex::new(threads),
+        }
+    }
+
+    /// Adds a route. Routes added after `shutdown()` are dropped right away.
+    ///
+    /// If the handler panics, its route is removed; other routes are unaffected.
+    pub fn add_route(&self, receiver: OpaqueIpcReceiver, mut callback: RouterHandler) -> RouteId {
+        self.add_event_route(receiver, Box::new(move |event| {
+            if let RouterEvent::Message(message) = event {
+                callback(message)
+            }
+        }))
+    }
+
+    /// Like `add_route()`, but the handler is also told when the channel is closed,
+    /// right before it is dropped.
+    ///
+    /// Routes removed with `remove_route()` or by `shutdown()` don't see a `Closed` event.
+    pub fn add_event_route(&self, receiver: OpaqueIpcReceiver, mut handler: RouterEventHandler)
+                           -> RouteId {
+        self.add_route_fn(receiver, Box::new(move |event| {
+            handler(event);
+            true
+        }))
+    }
+
+    fn add_route_fn(&self, receiver: OpaqueIpcReceiver, handler: RouteFn) -> RouteId {
+        let mut comm = self.comm.lock().unwrap();
+        let route_id = RouteId(comm.next_route_id);
+        comm.next_route_id += 1;
+        comm.send(RouterMsg::AddRoute(route_id, receiver, handler));
+        route_id
+    }
+
+    /// Like `add_route()`, but returns a handle that removes the route again when dropped.
+    pub fn add_route_with_handle(&self, receiver: OpaqueIpcReceiver, callback: RouterHandler)
+                                 -> RouteHandle {
+        RouteHandle {
+            proxy: self,
+            route_id: Some(self.add_route(receiver, callback)),
         }
     }
 
-    pub fn add_route(&self, receiver: OpaqueIpcReceiver, callback: RouterHandler) {
-        let comm = self.comm.lock().unwrap();
-        comm.msg_sender.send(RouterMsg::AddRoute(receiver, callback)).unwrap();
-        comm.wakeup_sender.send(()).unwrap();
+    /// Removes a route, dropping its handler and handing back its receiver.
+    ///
+    /// Returns `None` if the route doesn't exist (anymore), e.g. because its channel was closed.
+    ///
+    /// This waits for the router thread, so it must not be called from within a route handler.
+    pub fn remove_route(&self, route_id: RouteId) -> Option<OpaqueIpcReceiver> {
+        self.request_route_removal(route_id).recv().unwrap_or(None)
+    }
+
+    fn request_route_removal(&self, route_id: RouteId) -> Receiver<Option<OpaqueIpcReceiver>> {
+        let (reply_sender, reply_receiver) = mpsc::channel();
+        self.comm.lock().unwrap().send(RouterMsg::RemoveRoute(route_id, reply_sender));
+        reply_receiver
+    }
+
+    /// Stops the router thread, and the pool threads if any, and waits for them to exit.
+    ///
+    /// All remaining handlers are dropped in the order their routes were added,
+    /// together with their receivers. Handlers busy on a pool thread are dropped
+    /// once they return. Calling this more than once is harmless.
+    ///
+    /// When called from within a route handler, the router stops once the handler returns,
+    /// but there is no waiting for it.
+    pub fn shutdown(&self) {
+        self.comm.lock().unwrap().send(RouterMsg::Shutdown);
+        let threads = mem::replace(&mut *self.threads.lock().unwrap(), vec![]);
+        if threads.iter().all(|thread| thread.thread().id() != thread::current().id()) {
+            for thread in threads {
+                drop(thread.join())
+            }
+        }
     }
 
     /// A convenience function to route an `IpcReceiver<T>` to an existing `Sender<T>`.
+    ///
+    /// Messages that fail to deserialize are dropped.
     pub fn route_ipc_receiver_to_mpsc_sender<T>(&self,
                                                 ipc_receiver: IpcReceiver<T>,
                                                 mpsc_sender: Sender<T>)
@@ -51,11 +160,73 @@ impl RouterProxy {
                                                          Serialize +
                                                          Send +
                                                          'static {
+        self.route_ipc_receiver_to_mpsc_sender_with_policy(ipc_receiver,
+                                                           mpsc_sender,
+                                                           RouteErrorPolicy::Drop)
+    }
+
+    /// Like `route_ipc_receiver_to_mpsc_sender()`, with a choice of what happens to messages
+    /// that fail to deserialize.
+    pub fn route_ipc_receiver_to_mpsc_sender_with_policy<T>(&self,
+                                                            ipc_receiver: IpcReceiver<T>,
+                                                            mpsc_sender: Sender<T>,
+                                                            mut policy: RouteErrorPolicy)
+                                                            where T: for<'de> Deserialize<'de> +
+                                                                     Serialize +
+                                                                     Send +
+                                                                     'static {
+        self.add_route_fn(ipc_receiver.to_opaque(), Box::new(move |event| {
+            let message = match event {
+                RouterEvent::Message(message) => message,
+                RouterEvent::Closed => return false,
+            };
+            match message.to::<T>() {
+                Ok(value) => {
+                    drop(mpsc_sender.send(value));
+                    true
+                }
+                Err(error) => policy.handle(error),
+            }
+        }));
+    }
+
+    /// Routes an `IpcReceiver<T>` to an existing `Sender<Result<T, bincode::Error>>`,
+    /// so that messages which fail to deserialize show up as errors on the receiving end.
+    pub fn route_ipc_receiver_to_mpsc_result_sender<T>(&self,
+                                                       ipc_receiver: IpcReceiver<T>,
+                                                       mpsc_sender: Sender<Result<T,
+                                                                                  bincode::Error>>)
+                                                       where T: for<'de> Deserialize<'de> +
+                                                                Serialize +
+                                                                Send +
+                                                                'static {
         self.add_route(ipc_receiver.to_opaque(), Box::new(move |message| {
-            drop(mpsc_sender.send(message.to::<T>().unwrap()))
+            drop(mpsc_sender.send(message.to::<T>()))
+        }));
+    }
+
+    /// Routes an `OpaqueIpcReceiver` to an `OpaqueIpcSender`, passing the messages on
+    /// without deserializing them. The route is removed once sending fails.
+    pub fn forward_opaque(&self, receiver: OpaqueIpcReceiver, sender: OpaqueIpcSender)
+                          -> RouteId {
+        self.add_route_fn(receiver, Box::new(move |event| {
+            match event {
+                RouterEvent::Message(message) => sender.send_opaque(message).is_ok(),
+                RouterEvent::Closed => false,
+            }
         }))
     }
 
+    /// A convenience function to route an `IpcReceiver<T>` to an `IpcSender<T>`,
+    /// see `forward_opaque()`.
+    pub fn route_ipc_receiver_to_ipc_sender<T>(&self,
+                                               ipc_receiver: IpcReceiver<T>,
+                                               ipc_sender: IpcSender<T>)
+                                               -> RouteId
+                                               where T: for<'de> Deserialize<'de> + Serialize {
+        self.forward_opaque(ipc_receiver.to_opaque(), ipc_sender.to_opaque())
+    }
+
     /// A convenience function to route an `IpcReceiver<T>` to a `Receiver<T>`: the most common
     /// use of a `Router`.
     pub fn route_ipc_receiver_to_new_mpsc_receiver<T>(&self, ipc_receiver: IpcReceiver<T>)
@@ -70,27 +241,96 @@ impl RouterProxy {
     }
 }
 
+impl Drop for RouterProxy {
+    fn drop(&mut self) {
+        self.shutdown()
+    }
+}
+
 struct RouterProxyComm {
     msg_sender: Sender<RouterMsg>,
     wakeup_sender: IpcSender<()>,
+    next_route_id: u64,
+}
+
+impl RouterProxyComm {
+    fn send(&self, msg: RouterMsg) {
+        send_router_msg(&self.msg_sender, &self.wakeup_sender, msg)
+    }
+}
+
+/// Sends a message to the router thread; it is silently dropped if the router has shut down.
+fn send_router_msg(msg_sender: &Sender<RouterMsg>, wakeup_sender: &IpcSender<()>, msg: RouterMsg) {
+    if msg_sender.send(msg).is_ok() {
+        drop(wakeup_sender.send(()))
+    }
+}
+
+/// Identifies a route added with `RouterProxy::add_route()`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RouteId(u64);
+
+/// A route that is removed from the router when dropped,
+/// created by `RouterProxy::add_route_with_handle()`.
+pub struct RouteHandle<'a> {
+    proxy: &'a RouterProxy,
+    route_id: Option<RouteId>,
+}
+
+impl<'a> RouteHandle<'a> {
+    pub fn id(&self) -> RouteId {
+        self.route_id.unwrap()
+    }
+
+    /// Removes the route right away, handing back its receiver.
+    ///
+    /// The same caveats apply as for `RouterProxy::remove_route()`.
+    pub fn remove(mut self) -> Option<OpaqueIpcReceiver> {
+        self.proxy.remove_route(self.route_id.take().unwrap())
+    }
+
+    /// Keeps the route after all. It then stays until its channel is closed,
+    /// or it is removed with `RouterProxy::remove_route()`.
+    pub fn forget(mut self) -> RouteId {
+        self.route_id.take().unwrap()
+    }
+}
+
+impl<'a> Drop for RouteHandle<'a> {
+    fn drop(&mut self) {
+        if let Some(route_id) = self.route_id.take() {
+            // Don't wait for the router thread here,
+            // so handles can be dropped from within route handlers as well.
+            drop(self.proxy.request_route_removal(route_id))
+        }
+    }
 }
 
 struct Router {
     msg_receiver: Receiver<RouterMsg>,
     msg_wakeup_id: u64,
     ipc_receiver_set: IpcReceiverSet,
-    handlers: HashMap<u64,RouterHandler>,
+    routes: HashMap<u64,(RouteId, SharedRoute)>,
+    /// Receiver set IDs of the routes.
+    route_receiver_ids: HashMap<RouteId,u64>,
+    /// Hands routes with queued events to the pool threads, if there are any.
+    pool_sender: Option<Sender<(RouteId, SharedRoute)>>,
 }
 
 impl Router {
-    fn new(msg_receiver: Receiver<RouterMsg>, wakeup_receiver: IpcReceiver<()>) -> Router {
+    fn new(msg_receiver: Receiver<RouterMsg>,
+           wakeup_receiver: IpcReceiver<()>,
+           pool_sender: Option<Sender<(RouteId, SharedRoute)>>)
+           -> Router {
         let mut ipc_receiver_set = IpcReceiverSet::new().unwrap();
         let msg_wakeup_id = ipc_receiver_set.add(wakeup_receiver).unwrap();
         Router {
             msg_receiver: msg_receiver,
             msg_wakeup_id: msg_wakeup_id,
             ipc_receiver_set: ipc_receiver_set,
-            handlers: HashMap::new(),
+            routes: HashMap::new(),
+            route_receiver_ids: HashMap::new(),
+            pool_sender: pool_sender,
         }
     }
 
@@ -100,33 +340,260 @@ impl Router {
                 Ok(results) => results,
                 Err(_) => break,
             };
+            let mut wakeups = 0;
             for result in results.into_iter() {
                 match result {
                     IpcSelectionResult::MessageReceived(id, _) if id == self.msg_wakeup_id => {
-                        match self.msg_receiver.recv().unwrap() {
-                            RouterMsg::AddRoute(receiver, handler) => {
-                                let new_receiver_id = self.ipc_receiver_set
-                                                          .add_opaque(receiver)
-                                                          .unwrap();
-                                self.handlers.insert(new_receiver_id, handler);
-                            }
-                        }
+                        wakeups += 1;
                     }
                     IpcSelectionResult::MessageReceived(id, message) => {
-                        self.handlers.get_mut(&id).unwrap()(message)
+                        self.dispatch(id, message)
+                    }
+                    IpcSelectionResult::ChannelClosed(id) => self.close_route(id),
+                }
+            }
+            // Only handle router messages once the other results are dispatched,
+            // so messages already received for a route still reach it before it is removed.
+            for _ in 0..wakeups {
+                let msg = self.msg_receiver.recv().unwrap();
+                if !self.handle_msg(msg) {
+                    return self.drop_handlers();
+                }
+            }
+        }
+    }
+
+    /// Passes a message to the handler of a route, or queues it for the pool threads.
+    /// The route is removed if the handler asks for that, or panics.
+    fn dispatch(&mut self, id: u64, message: OpaqueIpcMessage) {
+        let keep_route = match self.routes.get(&id) {
+            Some(&(route_id, ref route)) => {
+                match self.pool_sender {
+                    Some(ref pool_sender) => {
+                        schedule(pool_sender, route_id, route, RouterEvent::Message(message));
+                        true
+                    }
+                    None => {
+                        let mut route = route.lock().unwrap();
+                        run_handler(route.handler.as_mut().unwrap(), RouterEvent::Message(message))
                     }
-                    IpcSelectionResult::ChannelClosed(id) => {
-                        self.handlers.remove(&id).unwrap();
+                }
+            }
+            None => return,
+        };
+        if !keep_route {
+            drop(self.remove_route(id))
+        }
+    }
+
+    fn close_route(&mut self, id: u64) {
+        // The route may be gone already if its handler gave up on it.
+        let (route_id, route) = match self.routes.remove(&id) {
+            Some(route) => route,
+            None => return,
+        };
+        self.route_receiver_ids.remove(&route_id);
+        match self.pool_sender {
+            Some(ref pool_sender) => {
+                schedule(pool_sender, route_id, &route, RouterEvent::Closed);
+                route.lock().unwrap().remove()
+            }
+            None => {
+                let handler = route.lock().unwrap().handler.take();
+                if let Some(mut handler) = handler {
+                    run_handler(&mut handler, RouterEvent::Closed);
+                }
+            }
+        }
+    }
+
+    fn remove_route(&mut self, id: u64) -> Option<OpaqueIpcReceiver> {
+        let (route_id, route) = self.routes.remove(&id).unwrap();
+        self.route_receiver_ids.remove(&route_id);
+        route.lock().unwrap().remove();
+        self.ipc_receiver_set.remove(id)
+    }
+
+    /// Returns `false` once the router should stop.
+    fn handle_msg(&mut self, msg: RouterMsg) -> bool {
+        match msg {
+            RouterMsg::AddRoute(route_id, receiver, handler) => {
+                let new_receiver_id = self.ipc_receiver_set.add_opaque(receiver).unwrap();
+                let route = Route {
+                    handler: Some(handler),
+                    events: VecDeque::new(),
+                    scheduled: false,
+                    removed: false,
+                };
+                self.routes.insert(new_receiver_id, (route_id, Arc::new(Mutex::new(route))));
+                self.route_receiver_ids.insert(route_id, new_receiver_id);
+            }
+            RouterMsg::RemoveRoute(route_id, reply_sender) => {
+                let receiver = match self.route_receiver_ids.get(&route_id) {
+                    Some(&id) => self.remove_route(id),
+                    None => None,
+                };
+                drop(reply_sender.send(receiver))
+            }
+            RouterMsg::Shutdown => return false,
+        }
+        true
+    }
+
+    /// Drops all handlers in the order their routes were added.
+    fn drop_handlers(&mut self) {
+        let mut routes: Vec<_> = self.routes.drain().map(|(_, route)| route).collect();
+        routes.sort_by_key(|&(route_id, _)| route_id);
+        self.route_receiver_ids.clear();
+        for (_, route) in routes {
+            let mut route = route.lock().unwrap();
+            route.events.clear();
+            route.remove();
+        }
+    }
+}
+
+/// A route's handler, along with the events queued for it when there are pool threads.
+struct Route {
+    /// `None` while a pool thread runs the handler, and once the route is gone.
+    handler: Option<RouteFn>,
+    events: VecDeque<RouterEvent>,
+    /// Whether a pool thread has been handed the route.
+    scheduled: bool,
+    /// Set once the route is removed. The handler is dropped after any queued events.
+    removed: bool,
+}
+
+impl Route {
+    fn remove(&mut self) {
+        self.removed = true;
+        if !self.scheduled {
+            self.handler = None
+        }
+    }
+}
+
+type SharedRoute = Arc<Mutex<Route>>;
+
+/// Queues an event for a route, handing the route to the pool threads unless it already is.
+/// Events for a removed route are dropped, as its handler may be gone already.
+fn schedule(pool_sender: &Sender<(RouteId, SharedRoute)>,
+            route_id: RouteId,
+            route: &SharedRoute,
+            event: RouterEvent) {
+    let mut route_state = route.lock().unwrap();
+    if route_state.removed {
+        return
+    }
+    route_state.events.push_back(event);
+    if !route_state.scheduled {
+        route_state.scheduled = true;
+        drop(pool_sender.send((route_id, route.clone())))
+    }
+}
+
+/// Returns whether to keep the route.
+fn run_handler(handler: &mut RouteFn, event: RouterEvent) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(|| handler(event))).unwrap_or(false)
+}
+
+fn run_pool_thread(routes: Arc<Mutex<Receiver<(RouteId, SharedRoute)>>>,
+                   msg_sender: Sender<RouterMsg>,
+                   wakeup_sender: IpcSender<()>) {
+    loop {
+        let (route_id, route) = match routes.lock().unwrap().recv() {
+            Ok(route) => route,
+            Err(_) => return,
+        };
+        if !run_queued_events(&route) {
+            let (reply_sender, _) = mpsc::channel();
+            send_router_msg(&msg_sender,
+                            &wakeup_sender,
+                            RouterMsg::RemoveRoute(route_id, reply_sender))
+        }
+    }
+}
+
+/// Runs the handler of a route until its queue is empty.
+/// Returns `false` if the handler asked for the route to be removed, or panicked.
+fn run_queued_events(route: &Mutex<Route>) -> bool {
+    loop {
+        let (event, mut handler) = {
+            let mut route = route.lock().unwrap();
+            let event = match route.events.pop_front() {
+                Some(event) => event,
+                None => {
+                    route.scheduled = false;
+                    if route.removed {
+                        route.handler = None
                     }
+                    return true
+                }
+            };
+            match route.handler.take() {
+                Some(handler) => (event, handler),
+                None => {
+                    // The handler gave up on the route already, so nobody wants the rest.
+                    route.events.clear();
+                    route.scheduled = false;
+                    return true
                 }
             }
+        };
+        let keep_route = run_handler(&mut handler, event);
+        let mut route = route.lock().unwrap();
+        if !keep_route {
+            route.events.clear();
+            route.scheduled = false;
+            route.removed = true;
+            return false
         }
+        route.handler = Some(handler);
     }
 }
 
 enum RouterMsg {
-    AddRoute(OpaqueIpcReceiver, RouterHandler),
+    AddRoute(RouteId, OpaqueIpcReceiver, RouteFn),
+    RemoveRoute(RouteId, Sender<Option<OpaqueIpcReceiver>>),
+    Shutdown,
 }
 
 pub type RouterHandler = Box<FnMut(OpaqueIpcMessage) + Send>;
 
+/// What happened on a route added with `RouterProxy::add_event_route()`.
+pub enum RouterEvent {
+    /// A message was received.
+    Message(OpaqueIpcMessage),
+    /// All senders were dropped, so the route is removed.
+    Closed,
+}
+
+pub type RouterEventHandler = Box<FnMut(RouterEvent) + Send>;
+
+/// How the router handles all route handlers internally: the result says whether to keep the route.
+type RouteFn = Box<FnMut(RouterEvent) -> bool + Send>;
+
+/// What the router does with messages that fail to deserialize.
+pub enum RouteErrorPolicy {
+    /// Drop the message and keep the route.
+    Drop,
+    /// Pass the error to a callback and keep the route.
+    Callback(Box<FnMut(bincode::Error) + Send>),
+    /// Remove the route, closing its receiver.
+    CloseRoute,
+}
+
+impl RouteErrorPolicy {
+    /// Returns whether to keep the route.
+    fn handle(&mut self, error: bincode::Error) -> bool {
+        match *self {
+            RouteErrorPolicy::Drop => true,
+            RouteErrorPolicy::Callback(ref mut callback) => {
+                callback(error);
+                true
+            }
+            RouteErrorPolicy::CloseRoute => false,
+        }
+    }
+}
+
@@ -7,10 +7,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use ipc::{self, IpcReceiverSet, IpcSender, IpcSharedMemory};
-#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
-use ipc::IpcReceiver;
-use router::ROUTER;
+use ipc::{self, IpcMessage, IpcReceiverSet, IpcSelectionResult, IpcSelector, IpcSender};
+use ipc::{IpcSharedMemory, IpcSyncSender, RecvError, RecvTimeoutError, SendErrorKind};
+use ipc::{IpcOneShotServer, IpcReceiver, SendTimeoutError, TryRecvError, TrySendError};
+use router::{ROUTER, RouteErrorPolicy, RouterEvent, RouterProxy};
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
 use libc;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -18,12 +18,27 @@ use std::cell::RefCell;
 use std::iter;
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{self, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
 
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+use ipc::{IpcServer, PeerPolicy};
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
-use ipc::IpcOneShotServer;
+use std::fs;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use ipc::IpcServerOptions;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use std::env;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use std::io::ErrorKind;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use std::process::{Command, Stdio};
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
 use std::io::Error;
@@ -137,6 +152,140 @@ fn select() {
     }
 }
 
+#[test]
+fn receiver_set_remove() {
+    let (tx0, rx0) = ipc::channel().unwrap();
+    let (tx1, rx1) = ipc::channel().unwrap();
+    let mut rx_set = IpcReceiverSet::new().unwrap();
+    let rx0_id = rx_set.add(rx0).unwrap();
+    let rx1_id = rx_set.add(rx1).unwrap();
+    assert_eq!(rx_set.len(), 2);
+
+    let rx0 = rx_set.remove(rx0_id).unwrap().to::<u32>();
+    assert!(!rx_set.contains(rx0_id));
+    assert!(rx_set.contains(rx1_id));
+    assert_eq!(rx_set.len(), 1);
+    assert!(rx_set.remove(rx0_id).is_none());
+
+    tx0.send(1).unwrap();
+    tx1.send(2).unwrap();
+    let (received_id, received_data) =
+        rx_set.select().unwrap().into_iter().next().unwrap().unwrap();
+    assert_eq!(received_id, rx1_id);
+    assert_eq!(received_data.to::<u32>().unwrap(), 2);
+    assert!(rx_set.try_select().unwrap().is_empty());
+    assert_eq!(rx0.recv().unwrap(), 1);
+
+    drop(tx1);
+    match rx_set.select().unwrap().into_iter().next() {
+        Some(IpcSelectionResult::ChannelClosed(id)) => assert_eq!(id, rx1_id),
+        _ => panic!("expected the channel to be closed"),
+    }
+    assert!(rx_set.is_empty());
+}
+
+#[test]
+fn selector() {
+    let (tx0, rx0) = ipc::channel::<u32>().unwrap();
+    let (tx1, rx1) = ipc::channel::<String>().unwrap();
+    let (mut numbers, mut strings, mut closed) = (vec![], vec![], 0);
+    {
+        let mut selector = IpcSelector::new().unwrap();
+        selector.add(rx0, |result| {
+            match result {
+                Ok(number) => numbers.push(number),
+                Err(RecvError::Disconnected) => closed += 1,
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }).unwrap();
+        selector.add(rx1, |result| strings.push(result.unwrap())).unwrap();
+        assert_eq!(selector.len(), 2);
+
+        tx0.send(42).unwrap();
+        tx1.send("hello".to_owned()).unwrap();
+        let mut handled = 0;
+        while handled < 2 {
+            handled += selector.select().unwrap();
+        }
+        assert_eq!(selector.try_select().unwrap(), 0);
+
+        drop(tx0);
+        selector.select().unwrap();
+        assert_eq!(selector.len(), 1);
+    }
+    assert_eq!(numbers, vec![42]);
+    assert_eq!(strings, vec!["hello".to_owned()]);
+    assert_eq!(closed, 1);
+    drop(tx1);
+}
+
+#[test]
+fn opaque_message() {
+    let (tx, rx) = ipc::channel().unwrap();
+    let mut rx_set = IpcReceiverSet::new().unwrap();
+    rx_set.add(rx).unwrap();
+
+    tx.send(42u32).unwrap();
+    let (_, message) = rx_set.select().unwrap().into_iter().next().unwrap().unwrap();
+    assert_eq!(message.data(), &[42, 0, 0, 0]);
+    assert_eq!(message.channel_count(), 0);
+    assert_eq!(message.shared_memory_region_count(), 0);
+
+    let (reply_tx, reply_rx) = ipc::channel().unwrap();
+    let (relayed_tx, relayed_rx) = ipc::channel::<(IpcSender<u32>, IpcSharedMemory)>().unwrap();
+    let tx = tx.to_opaque().to::<(IpcSender<u32>, IpcSharedMemory)>();
+    tx.send((reply_tx, IpcSharedMemory::from_byte(0xba, 1024))).unwrap();
+    let (_, message) = rx_set.select().unwrap().into_iter().next().unwrap().unwrap();
+    assert_eq!(message.channel_count(), 1);
+    assert_eq!(message.shared_memory_region_count(), 1);
+    relayed_tx.to_opaque().send_opaque(message).unwrap();
+
+    let (reply_tx, shared_memory) = relayed_rx.recv().unwrap();
+    assert!(shared_memory.iter().all(|byte| *byte == 0xba));
+    reply_tx.send(42).unwrap();
+    assert_eq!(reply_rx.recv().unwrap(), 42);
+}
+
+#[test]
+fn try_select() {
+    let (tx, rx) = ipc::channel().unwrap();
+    let mut rx_set = IpcReceiverSet::new().unwrap();
+    let rx_id = rx_set.add(rx).unwrap();
+    assert!(rx_set.try_select().unwrap().is_empty());
+
+    tx.send(42u32).unwrap();
+    let (received_id, received_data) =
+        rx_set.try_select().unwrap().into_iter().next().unwrap().unwrap();
+    assert_eq!(received_id, rx_id);
+    assert_eq!(received_data.to::<u32>().unwrap(), 42);
+
+    drop(tx);
+    match rx_set.try_select().unwrap().into_iter().next() {
+        Some(IpcSelectionResult::ChannelClosed(id)) => assert_eq!(id, rx_id),
+        _ => panic!("expected the channel to be closed"),
+    }
+}
+
+#[test]
+fn select_timeout() {
+    let (tx, rx) = ipc::channel().unwrap();
+    let mut rx_set = IpcReceiverSet::new().unwrap();
+    let rx_id = rx_set.add(rx).unwrap();
+    let start = Instant::now();
+    assert!(rx_set.select_timeout(Duration::from_millis(50)).unwrap().is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        tx.send(42u32).unwrap();
+    });
+    let (received_id, received_data) = rx_set.select_timeout(Duration::from_secs(10))
+                                             .unwrap().into_iter().next().unwrap().unwrap();
+    assert_eq!(received_id, rx_id);
+    assert_eq!(received_data.to::<u32>().unwrap(), 42);
+    thread.join().unwrap();
+}
+
 #[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
 #[test]
 fn cross_process_embedded_senders() {
@@ -266,6 +415,175 @@ fn router_drops_callbacks_on_cloned_sender_shutdown() {
     assert_eq!(drop_rx.recv(), Ok(42));
 }
 
+#[test]
+fn router_event_route() {
+    let person = ("Patrick Walton".to_owned(), 29);
+    let (tx, rx) = ipc::channel().unwrap();
+    let (event_sender, event_receiver) = mpsc::channel();
+    ROUTER.add_event_route(rx.to_opaque(), Box::new(move |event| {
+        event_sender.send(match event {
+            RouterEvent::Message(message) => Some(message.to::<Person>().unwrap()),
+            RouterEvent::Closed => None,
+        }).unwrap()
+    }));
+    tx.send(person.clone()).unwrap();
+    assert_eq!(event_receiver.recv().unwrap(), Some(person));
+    drop(tx);
+    assert_eq!(event_receiver.recv().unwrap(), None);
+    assert!(event_receiver.recv().is_err());
+}
+
+#[test]
+fn router_error_policy() {
+    let (tx, rx) = ipc::channel::<bool>().unwrap();
+    let bad_tx: IpcSender<u8> = tx.clone().to_opaque().to();
+    let (mpsc_tx, mpsc_rx) = mpsc::channel();
+    ROUTER.route_ipc_receiver_to_mpsc_sender(rx, mpsc_tx);
+    bad_tx.send(2).unwrap();
+    tx.send(true).unwrap();
+    assert_eq!(mpsc_rx.recv().unwrap(), true);
+
+    let (tx, rx) = ipc::channel::<bool>().unwrap();
+    let bad_tx: IpcSender<u8> = tx.clone().to_opaque().to();
+    let (mpsc_tx, mpsc_rx) = mpsc::channel();
+    ROUTER.route_ipc_receiver_to_mpsc_result_sender(rx, mpsc_tx);
+    bad_tx.send(2).unwrap();
+    tx.send(true).unwrap();
+    assert!(mpsc_rx.recv().unwrap().is_err());
+    assert_eq!(mpsc_rx.recv().unwrap().unwrap(), true);
+
+    let (tx, rx) = ipc::channel::<bool>().unwrap();
+    let bad_tx: IpcSender<u8> = tx.clone().to_opaque().to();
+    let (mpsc_tx, mpsc_rx) = mpsc::channel();
+    let (error_tx, error_rx) = mpsc::channel();
+    let policy = RouteErrorPolicy::Callback(Box::new(move |error| {
+        error_tx.send(error.to_string()).unwrap()
+    }));
+    ROUTER.route_ipc_receiver_to_mpsc_sender_with_policy(rx, mpsc_tx, policy);
+    bad_tx.send(2).unwrap();
+    tx.send(true).unwrap();
+    assert!(error_rx.recv().is_ok());
+    assert_eq!(mpsc_rx.recv().unwrap(), true);
+
+    let (tx, rx) = ipc::channel::<bool>().unwrap();
+    let bad_tx: IpcSender<u8> = tx.clone().to_opaque().to();
+    let (mpsc_tx, mpsc_rx) = mpsc::channel();
+    ROUTER.route_ipc_receiver_to_mpsc_sender_with_policy(rx,
+                                                         mpsc_tx,
+                                                         RouteErrorPolicy::CloseRoute);
+    bad_tx.send(2).unwrap();
+    assert!(mpsc_rx.recv().is_err());
+}
+
+#[test]
+fn router_survives_handler_panic() {
+    let (tx, rx) = ipc::channel::<()>().unwrap();
+    let (mpsc_tx, mpsc_rx) = mpsc::channel::<()>();
+    ROUTER.add_route(rx.to_opaque(), Box::new(move |_| {
+        let _mpsc_tx = &mpsc_tx;
+        panic!("handler panic")
+    }));
+    tx.send(()).unwrap();
+    assert!(mpsc_rx.recv().is_err());
+
+    let (tx, rx) = ipc::channel().unwrap();
+    let mpsc_rx = ROUTER.route_ipc_receiver_to_new_mpsc_receiver(rx);
+    tx.send(42).unwrap();
+    assert_eq!(mpsc_rx.recv().unwrap(), 42);
+}
+
+#[test]
+fn router_thread_pool_route_gives_up() {
+    let router = RouterProxy::with_thread_pool(1);
+    for _ in 0..20 {
+        let (tx, rx) = ipc::channel::<bool>().unwrap();
+        let bad_tx: IpcSender<u8> = tx.clone().to_opaque().to();
+        let (mpsc_tx, mpsc_rx) = mpsc::channel();
+        router.route_ipc_receiver_to_mpsc_sender_with_policy(rx,
+                                                             mpsc_tx,
+                                                             RouteErrorPolicy::CloseRoute);
+        bad_tx.send(2).unwrap();
+        // These keep arriving while the route is being removed, if they get through at all.
+        for _ in 0..20 {
+            drop(tx.send(true));
+        }
+        drop(tx);
+        drop(bad_tx);
+        assert!(mpsc_rx.recv().is_err());
+    }
+
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    let mpsc_rx = router.route_ipc_receiver_to_new_mpsc_receiver(rx);
+    tx.send(42).unwrap();
+    assert_eq!(mpsc_rx.recv().unwrap(), 42);
+}
+
+#[test]
+fn router_thread_pool() {
+    let router = RouterProxy::with_thread_pool(4);
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut senders = vec![];
+    for route in 0..4 {
+        let (tx, rx) = ipc::channel::<u32>().unwrap();
+        let done_tx = done_tx.clone();
+        let mut expected = 0;
+        router.add_route(rx.to_opaque(), Box::new(move |message| {
+            assert_eq!(message.to::<u32>().unwrap(), expected);
+            expected += 1;
+            if expected == 100 {
+                done_tx.send(route).unwrap()
+            }
+        }));
+        senders.push(tx);
+    }
+    drop(done_tx);
+    for i in 0..100 {
+        for tx in &senders {
+            tx.send(i).unwrap();
+        }
+    }
+    let mut done: Vec<_> = done_rx.iter().take(4).collect();
+    done.sort();
+    assert_eq!(done, vec![0, 1, 2, 3]);
+
+    // A handler blocking on another route's handler only works with more than one thread.
+    let (slow_tx, slow_rx) = ipc::channel::<()>().unwrap();
+    let (fast_tx, fast_rx) = ipc::channel::<()>().unwrap();
+    let (unblock_tx, unblock_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    router.add_route(slow_rx.to_opaque(), Box::new(move |_| {
+        unblock_rx.recv().unwrap();
+        done_tx.send(()).unwrap()
+    }));
+    router.add_route(fast_rx.to_opaque(), Box::new(move |_| unblock_tx.send(()).unwrap()));
+    slow_tx.send(()).unwrap();
+    fast_tx.send(()).unwrap();
+    done_rx.recv().unwrap();
+
+    router.shutdown();
+    assert!(slow_tx.send(()).is_err());
+}
+
+#[test]
+fn router_forward_opaque() {
+    let (tx, rx) = ipc::channel::<(IpcSender<u32>, IpcSharedMemory)>().unwrap();
+    let (forwarded_tx, forwarded_rx) = ipc::channel().unwrap();
+    ROUTER.route_ipc_receiver_to_ipc_sender(rx, forwarded_tx);
+
+    let (reply_tx, reply_rx) = ipc::channel().unwrap();
+    tx.send((reply_tx, IpcSharedMemory::from_byte(0xba, 1024))).unwrap();
+    let (reply_tx, shared_memory) = forwarded_rx.recv().unwrap();
+    assert!(shared_memory.iter().all(|byte| *byte == 0xba));
+    reply_tx.send(42).unwrap();
+    assert_eq!(reply_rx.recv().unwrap(), 42);
+
+    drop(tx);
+    match forwarded_rx.recv() {
+        Err(RecvError::Disconnected) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
 #[test]
 fn router_big_data() {
     let person = ("Patrick Walton".to_owned(), 29);
@@ -285,6 +603,88 @@ fn router_big_data() {
     thread.join().unwrap();
 }
 
+#[test]
+fn router_remove_route() {
+    let person = ("Patrick Walton".to_owned(), 29);
+    let (tx, rx) = ipc::channel().unwrap();
+    let (callback_fired_sender, callback_fired_receiver) = mpsc::channel::<Person>();
+    let route_id = ROUTER.add_route(rx.to_opaque(), Box::new(move |person| {
+        callback_fired_sender.send(person.to().unwrap()).unwrap()
+    }));
+    tx.send(person.clone()).unwrap();
+    assert_eq!(callback_fired_receiver.recv().unwrap(), person);
+
+    let rx: IpcReceiver<Person> = ROUTER.remove_route(route_id).unwrap().to();
+    assert!(callback_fired_receiver.recv().is_err());
+    tx.send(person.clone()).unwrap();
+    assert_eq!(rx.recv().unwrap(), person);
+    assert!(ROUTER.remove_route(route_id).is_none());
+}
+
+#[test]
+fn router_route_handle() {
+    let (tx, rx) = ipc::channel::<()>().unwrap();
+    let handle = ROUTER.add_route_with_handle(rx.to_opaque(), Box::new(|_| ()));
+    let rx: IpcReceiver<()> = handle.remove().unwrap().to();
+    tx.send(()).unwrap();
+    assert_eq!(rx.recv().unwrap(), ());
+
+    let handle = ROUTER.add_route_with_handle(rx.to_opaque(), Box::new(|_| ()));
+    let route_id = handle.id();
+    drop(handle);
+    assert!(ROUTER.remove_route(route_id).is_none());
+
+    let (_tx, rx) = ipc::channel::<()>().unwrap();
+    let route_id = ROUTER.add_route_with_handle(rx.to_opaque(), Box::new(|_| ())).forget();
+    assert!(ROUTER.remove_route(route_id).is_some());
+}
+
+#[test]
+fn router_shutdown() {
+    struct Dropper {
+        id: i32,
+        sender: Sender<i32>,
+    }
+
+    impl Drop for Dropper {
+        fn drop(&mut self) {
+            self.sender.send(self.id).unwrap()
+        }
+    }
+
+    let router = RouterProxy::new();
+    let (drop_tx, drop_rx) = mpsc::channel();
+    let mut senders = vec![];
+    for id in 0..3 {
+        let (tx, rx) = ipc::channel::<()>().unwrap();
+        let dropper = Dropper {
+            id: id,
+            sender: drop_tx.clone(),
+        };
+        router.add_route(rx.to_opaque(), Box::new(move |_| drop(&dropper)));
+        senders.push(tx);
+    }
+    router.shutdown();
+    assert_eq!(drop_rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    for tx in senders {
+        assert!(tx.send(()).is_err());
+    }
+
+    router.shutdown();
+    let (_tx, rx) = ipc::channel::<()>().unwrap();
+    let route_id = router.add_route(rx.to_opaque(), Box::new(|_| ()));
+    assert!(router.remove_route(route_id).is_none());
+}
+
+#[test]
+fn router_drop_shuts_down() {
+    let router = RouterProxy::new();
+    let (tx, rx) = ipc::channel::<()>().unwrap();
+    router.add_route(rx.to_opaque(), Box::new(|_| ()));
+    drop(router);
+    assert!(tx.send(()).is_err());
+}
+
 #[test]
 fn shared_memory() {
     let person = ("Patrick Walton".to_owned(), 29);
@@ -298,6 +698,28 @@ fn shared_memory() {
     assert!(received_person_and_shared_memory.1.iter().all(|byte| *byte == 0xba));
 }
 
+#[test]
+fn prepared_message() {
+    let (reply_tx, reply_rx) = ipc::channel().unwrap();
+    let data = ("Patrick Walton".to_owned(), reply_tx, IpcSharedMemory::from_byte(0xba, 1024));
+    let message = IpcMessage::new(&data).unwrap();
+    let channels: Vec<_> = (0..3).map(|_| ipc::channel().unwrap()).collect();
+    for &(ref tx, _) in &channels {
+        tx.send_message(&message).unwrap();
+    }
+    for (i, (_, rx)) in channels.into_iter().enumerate() {
+        let (name, reply_tx, shared_memory): (String, IpcSender<usize>, IpcSharedMemory) =
+            rx.recv().unwrap();
+        assert_eq!(name, data.0);
+        assert!(shared_memory.iter().all(|byte| *byte == 0xba));
+        reply_tx.send(i).unwrap();
+        assert_eq!(reply_rx.recv().unwrap(), i);
+    }
+
+    let (_, rx) = ipc::channel::<()>().unwrap();
+    assert!(IpcMessage::new(&rx).is_err());
+}
+
 #[test]
 fn opaque_sender() {
     let person = ("Patrick Walton".to_owned(), 29);
@@ -334,6 +756,736 @@ fn try_recv() {
     assert!(rx.try_recv().is_err());
 }
 
+#[test]
+fn try_recv_empty_then_disconnected() {
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    match rx.try_recv() {
+        Err(TryRecvError::Empty) => {}
+        result => panic!("expected empty channel, got {:?}", result),
+    }
+    drop(tx);
+    match rx.try_recv() {
+        Err(TryRecvError::Disconnected) => {}
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn recv_disconnected() {
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    drop(tx);
+    match rx.recv() {
+        Err(RecvError::Disconnected) => {}
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn recv_deserialize_error() {
+    let (tx, rx) = ipc::channel::<String>().unwrap();
+    let tx = tx.to_opaque().to::<Vec<u32>>();
+    tx.send(vec![0xffffffff]).unwrap();
+    match rx.recv() {
+        Err(RecvError::Deserialize(_)) => {}
+        result => panic!("expected deserialization error, got {:?}", result),
+    }
+}
+
+#[test]
+fn corrupt_channel_index() {
+    let (tx, rx) = ipc::channel::<IpcSender<u32>>().unwrap();
+    let tx = tx.to_opaque().to::<u64>();
+    tx.send(5).unwrap();
+    match rx.recv() {
+        Err(RecvError::Deserialize(_)) => {}
+        result => panic!("expected deserialization error, got {:?}", result),
+    }
+}
+
+#[test]
+fn corrupt_shared_memory_index() {
+    let (tx, rx) = ipc::channel::<IpcSharedMemory>().unwrap();
+    let tx = tx.to_opaque().to::<u64>();
+    tx.send(0).unwrap();
+    match rx.recv() {
+        Err(RecvError::Deserialize(_)) => {}
+        result => panic!("expected deserialization error, got {:?}", result),
+    }
+}
+
+#[test]
+fn channel_index_claimed_twice() {
+    let (tx, rx) = ipc::channel::<(IpcSender<u32>, IpcSender<u32>)>().unwrap();
+    let tx = tx.to_opaque().to::<(IpcSender<u32>, u64)>();
+    let (sub_tx, _sub_rx) = ipc::channel().unwrap();
+    tx.send((sub_tx, 0)).unwrap();
+    match rx.recv() {
+        Err(RecvError::Deserialize(_)) => {}
+        result => panic!("expected deserialization error, got {:?}", result),
+    }
+}
+
+#[test]
+fn shared_memory_index_claimed_twice() {
+    let (tx, rx) = ipc::channel::<(IpcSharedMemory, IpcSharedMemory)>().unwrap();
+    let tx = tx.to_opaque().to::<(IpcSharedMemory, u64)>();
+    tx.send((IpcSharedMemory::from_byte(0xba, 16), 0)).unwrap();
+    match rx.recv() {
+        Err(RecvError::Deserialize(_)) => {}
+        result => panic!("expected deserialization error, got {:?}", result),
+    }
+}
+
+#[test]
+fn unclaimed_channels_are_closed() {
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    let tx = tx.to_opaque().to::<(u32, IpcSender<u32>)>();
+    let (sub_tx, sub_rx) = ipc::channel::<u32>().unwrap();
+    tx.send((7, sub_tx)).unwrap();
+    assert_eq!(rx.recv().unwrap(), 7);
+    match sub_rx.recv() {
+        Err(RecvError::Disconnected) => {}
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn to_with_unclaimed() {
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    let tx = tx.to_opaque().to::<(u32, IpcSender<u32>, IpcSharedMemory)>();
+    let (sub_tx, sub_rx) = ipc::channel::<u32>().unwrap();
+    tx.send((7, sub_tx, IpcSharedMemory::from_byte(0xba, 16))).unwrap();
+    let mut rx_set = IpcReceiverSet::new().unwrap();
+    rx_set.add(rx).unwrap();
+    let (_, received_data) = rx_set.select().unwrap().into_iter().next().unwrap().unwrap();
+    let (result, unclaimed) = received_data.to_with_unclaimed::<u32>();
+    assert_eq!(result.unwrap(), 7);
+    assert_eq!(unclaimed, 2);
+    assert!(sub_rx.recv().is_err());
+}
+
+#[test]
+fn send_disconnected() {
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    drop(rx);
+    match tx.send(42) {
+        Err(err) => {
+            match *err.kind() {
+                SendErrorKind::Disconnected => {}
+                ref kind => panic!("expected disconnected channel, got {:?}", kind),
+            }
+            assert_eq!(err.into_inner(), 42);
+        }
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn send_ref() {
+    let person = ("Patrick Walton".to_owned(), 29);
+    let (tx, rx) = ipc::channel().unwrap();
+    tx.send_ref(&person).unwrap();
+    assert_eq!(rx.recv().unwrap(), person);
+
+    drop(rx);
+    match tx.send_ref(&person) {
+        Err(SendErrorKind::Disconnected) => {}
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn recv_timeout() {
+    let person = ("Patrick Walton".to_owned(), 29);
+    let (tx, rx) = ipc::channel::<Person>().unwrap();
+    match rx.recv_timeout(Duration::from_millis(10)) {
+        Err(RecvTimeoutError::Timeout) => {}
+        result => panic!("expected timeout, got {:?}", result),
+    }
+    tx.send(person.clone()).unwrap();
+    let received_person = rx.recv_timeout(Duration::from_millis(10)).unwrap();
+    assert_eq!(person, received_person);
+    drop(tx);
+    match rx.recv_deadline(Instant::now() + Duration::from_secs(10)) {
+        Err(RecvTimeoutError::Disconnected) => {}
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn bytes_recv_timeout() {
+    let bytes = [1, 2, 3, 4, 5, 6, 7];
+    let (tx, rx) = ipc::bytes_channel().unwrap();
+    match rx.recv_timeout(Duration::from_millis(10)) {
+        Err(RecvTimeoutError::Timeout) => {}
+        result => panic!("expected timeout, got {:?}", result),
+    }
+    tx.send(&bytes[..]).unwrap();
+    let received_bytes = rx.recv_deadline(Instant::now() + Duration::from_millis(10)).unwrap();
+    assert_eq!(&bytes, &received_bytes[..]);
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn try_send_full() {
+    let (tx, rx) = ipc::channel().unwrap();
+    let data = vec![0xba; 1024];
+    let mut sent = 0;
+    loop {
+        match tx.try_send(&data) {
+            Ok(()) => sent += 1,
+            Err(TrySendError::Full) => break,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+    assert!(sent > 0);
+    assert_eq!(rx.recv().unwrap(), data);
+    tx.try_send(&data).unwrap();
+    for _ in 0..sent {
+        assert_eq!(rx.recv().unwrap(), data);
+    }
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn bytes_send_timeout() {
+    let (tx, rx) = ipc::bytes_channel().unwrap();
+    let data = [0xba; 1024];
+    while tx.try_send(&data).is_ok() {}
+    let start = Instant::now();
+    match tx.send_timeout(&data, Duration::from_millis(50)) {
+        Err(SendTimeoutError::Timeout) => {}
+        result => panic!("expected timeout, got {:?}", result),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    let thread = thread::spawn(move || {
+        while rx.recv().is_ok() {}
+    });
+    tx.send_timeout(&data, Duration::from_secs(10)).unwrap();
+    drop(tx);
+    thread.join().unwrap();
+}
+
+#[test]
+fn sync_channel_bounded() {
+    let (tx, rx) = ipc::sync_channel(2).unwrap();
+    tx.try_send(&1).unwrap();
+    tx.try_send(&2).unwrap();
+    match tx.try_send(&3) {
+        Err(TrySendError::Full) => {}
+        result => panic!("expected full channel, got {:?}", result),
+    }
+    assert_eq!(rx.recv().unwrap(), 1);
+    tx.try_send(&3).unwrap();
+    assert_eq!(rx.recv().unwrap(), 2);
+    assert_eq!(rx.recv().unwrap(), 3);
+}
+
+#[test]
+fn sync_channel_blocking_send() {
+    let (tx, rx) = ipc::sync_channel(1).unwrap();
+    let thread = thread::spawn(move || {
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+    });
+    for i in 0..10 {
+        assert_eq!(rx.recv().unwrap(), i);
+    }
+    thread.join().unwrap();
+}
+
+#[test]
+fn sync_channel_rendezvous() {
+    let (tx, rx) = ipc::sync_channel(0).unwrap();
+    match tx.try_send(&1) {
+        Err(TrySendError::Full) => {}
+        result => panic!("expected full channel, got {:?}", result),
+    }
+    let thread = thread::spawn(move || {
+        tx.send(2).unwrap();
+        tx
+    });
+    assert_eq!(rx.recv().unwrap(), 2);
+    let tx = thread.join().unwrap();
+    drop(rx);
+    match tx.send(3) {
+        Err(err) => {
+            match *err.kind() {
+                SendErrorKind::Disconnected => {}
+                ref kind => panic!("expected disconnected channel, got {:?}", kind),
+            }
+            assert_eq!(err.into_inner(), 3);
+        }
+        result => panic!("expected disconnected channel, got {:?}", result),
+    }
+}
+
+#[test]
+fn sync_channel_transferred_sender() {
+    let (tx, rx) = ipc::sync_channel(1).unwrap();
+    let (super_tx, super_rx) = ipc::channel::<IpcSyncSender<u32>>().unwrap();
+    super_tx.send(tx).unwrap();
+    let tx = super_rx.recv().unwrap();
+    tx.try_send(&1).unwrap();
+    match tx.try_send(&2) {
+        Err(TrySendError::Full) => {}
+        result => panic!("expected full channel, got {:?}", result),
+    }
+    assert_eq!(rx.recv().unwrap(), 1);
+    tx.try_send(&2).unwrap();
+    assert_eq!(rx.recv().unwrap(), 2);
+}
+
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn sync_channel_shared_capacity() {
+    let (tx, rx) = ipc::sync_channel(2).unwrap();
+    let (super_tx, super_rx) = ipc::channel::<IpcSyncSender<u32>>().unwrap();
+    super_tx.send(tx.clone()).unwrap();
+    let transferred_tx = super_rx.recv().unwrap();
+    let cloned_tx = tx.clone();
+
+    tx.try_send(&1).unwrap();
+    transferred_tx.try_send(&2).unwrap();
+    for sender in &[&tx, &transferred_tx, &cloned_tx] {
+        match sender.try_send(&3) {
+            Err(TrySendError::Full) => {}
+            result => panic!("expected full channel, got {:?}", result),
+        }
+    }
+    assert_eq!(rx.recv().unwrap(), 1);
+    cloned_tx.try_send(&3).unwrap();
+    match transferred_tx.try_send(&4) {
+        Err(TrySendError::Full) => {}
+        result => panic!("expected full channel, got {:?}", result),
+    }
+    assert_eq!(rx.recv().unwrap(), 2);
+    assert_eq!(rx.recv().unwrap(), 3);
+}
+
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn sync_channel_try_send_during_send() {
+    let (tx, rx) = ipc::sync_channel(1).unwrap();
+    let (super_tx, super_rx) = ipc::channel::<IpcSyncSender<u32>>().unwrap();
+    super_tx.send(tx.clone()).unwrap();
+    let transferred_tx = super_rx.recv().unwrap();
+
+    let receiver = thread::spawn(move || {
+        let mut count = 0;
+        while let Ok(data) = rx.recv() {
+            count += data;
+        }
+        count
+    });
+    // Non-blocking attempts on one sender mustn't make blocking sends on another one fail.
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let spammer = thread::spawn(move || {
+        while let Err(mpsc::TryRecvError::Empty) = stop_rx.try_recv() {
+            drop(tx.try_send(&0));
+        }
+    });
+    for _ in 0..2000 {
+        transferred_tx.send(1).unwrap();
+    }
+    stop_tx.send(()).unwrap();
+    spammer.join().unwrap();
+    drop(transferred_tx);
+    assert_eq!(receiver.join().unwrap(), 2000);
+}
+
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn sync_channel_large_capacity() {
+    // Far more than a Unix socket buffers.
+    match ipc::sync_channel::<u8>(1000000) {
+        Err(ref err) if err.kind() == ErrorKind::InvalidInput => {}
+        result => panic!("expected the capacity to be rejected, got {:?}", result.map(|_| ())),
+    }
+
+    // Whatever is accepted can be used to the full.
+    let capacity = 100;
+    let (tx, rx) = ipc::sync_channel::<u8>(capacity).unwrap();
+    for _ in 0..capacity {
+        tx.try_send(&0).unwrap();
+    }
+    match tx.try_send(&0) {
+        Err(TrySendError::Full) => {}
+        result => panic!("expected full channel, got {:?}", result),
+    }
+    rx.recv().unwrap();
+    tx.try_send(&0).unwrap();
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn cross_process_sync_sender() {
+    let (server, name) = IpcOneShotServer::new().unwrap();
+    let (result_tx, result_rx) = ipc::channel().unwrap();
+    let child_pid = unsafe { fork(|| {
+        let (tx, rx) = ipc::sync_channel::<u32>(1).unwrap();
+        let tx0 = IpcSender::connect(name).unwrap();
+        tx0.send(tx).unwrap();
+        let received: Vec<u32> = (0..5).map(|_| rx.recv().unwrap()).collect();
+        result_tx.send(received).unwrap();
+    })};
+    let (_, tx): (_, IpcSyncSender<u32>) = server.accept().unwrap();
+    for i in 0..5 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(result_rx.recv().unwrap(), vec![0, 1, 2, 3, 4]);
+    child_pid.wait();
+}
+
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+#[test]
+fn server_multiple_clients() {
+    let (server, name) = IpcServer::new().unwrap();
+    let clients: Vec<_> = (0..3u32).map(|i| {
+        let name = name.clone();
+        thread::spawn(move || {
+            let tx = IpcSender::connect(name).unwrap();
+            tx.send(i).unwrap();
+            tx.send(i * 10).unwrap();
+        })
+    }).collect();
+    let mut received: Vec<_> = server.incoming().take(3).map(|client| {
+        let rx = client.unwrap();
+        (rx.recv().unwrap(), rx.recv().unwrap())
+    }).collect();
+    received.sort();
+    assert_eq!(received, vec![(0, 0), (1, 10), (2, 20)]);
+    for client in clients {
+        client.join().unwrap();
+    }
+}
+
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+#[test]
+fn server_silent_client() {
+    let (server, name) = IpcServer::<u32>::new().unwrap();
+    let silent_tx = IpcSender::<u32>::connect(name.clone()).unwrap();
+    let silent_rx = server.accept().unwrap();
+    let tx = IpcSender::connect(name).unwrap();
+    tx.send(42).unwrap();
+    let rx = server.accept().unwrap();
+    assert_eq!(rx.recv().unwrap(), 42);
+    match silent_rx.try_recv() {
+        Err(TryRecvError::Empty) => (),
+        v => panic!("Silent client sent something: {:?}", v),
+    }
+    drop(silent_tx);
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn cross_process_server() {
+    let (server, name) = IpcServer::new().unwrap();
+    let child_pids: Vec<_> = (0..2u32).map(|i| {
+        let name = name.clone();
+        unsafe { fork(|| {
+            let tx = IpcSender::connect(name).unwrap();
+            tx.send(i).unwrap();
+        })}
+    }).collect();
+    let mut received: Vec<u32> = (0..2).map(|_| server.accept().unwrap().recv().unwrap())
+                                       .collect();
+    received.sort();
+    assert_eq!(received, vec![0, 1]);
+    for child_pid in child_pids {
+        child_pid.wait();
+    }
+
+    assert!(fs::metadata(&name).is_ok());
+    drop(server);
+    assert!(fs::metadata(&name).is_err());
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn connect_timeout() {
+    let (server, name) = IpcOneShotServer::<u32>::new().unwrap();
+    let client_name = name.clone();
+    let client = thread::spawn(move || {
+        let tx = IpcSender::connect_timeout(client_name, Duration::from_secs(5)).unwrap();
+        tx.send(42).unwrap();
+    });
+    assert_eq!(server.accept().unwrap().1, 42);
+    client.join().unwrap();
+
+    // The server is gone now.
+    let timeout = Duration::from_millis(100);
+    let start = Instant::now();
+    assert!(IpcSender::<u32>::connect_timeout(name, timeout).is_err());
+    assert!(start.elapsed() >= timeout);
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn connect_timeout_overflow() {
+    let (server, name) = IpcOneShotServer::<u32>::new().unwrap();
+    let client = thread::spawn(move || {
+        let tx = IpcSender::connect_timeout(name, Duration::new(u64::max_value(), 0)).unwrap();
+        tx.send(42).unwrap();
+    });
+    assert_eq!(server.accept().unwrap().1, 42);
+    client.join().unwrap();
+}
+
+#[test]
+fn connect_invalid_name() {
+    assert!(IpcSender::<u32>::connect("invalid\0name".to_owned()).is_err());
+    assert!(IpcSender::<u32>::connect_timeout("invalid\0name".to_owned(),
+                                              Duration::from_millis(100)).is_err());
+}
+
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn server_options() {
+    let directory = env::temp_dir().join(format!("ipc-channel-test.{}", unsafe { libc::getpid() }));
+    fs::create_dir(&directory).unwrap();
+    let options = IpcServerOptions {
+        directory: Some(directory.clone()),
+        mode: Some(0o600),
+        ..Default::default()
+    };
+    let (server, name) = IpcOneShotServer::<i32>::with_options(&options).unwrap();
+    assert!(name.starts_with(directory.to_str().unwrap()));
+    assert_eq!(fs::metadata(&name).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let client_name = name.clone();
+    let client = thread::spawn(move || {
+        let tx = IpcSender::connect(client_name).unwrap();
+        tx.send(42).unwrap();
+    });
+    let (_, data) = server.accept().unwrap();
+    assert_eq!(data, 42);
+    client.join().unwrap();
+
+    assert!(fs::metadata(&name).is_err());
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[cfg(all(not(feature = "force-inprocess"), target_os = "linux"))]
+#[test]
+fn server_abstract_namespace() {
+    let options = IpcServerOptions {
+        abstract_namespace: true,
+        ..Default::default()
+    };
+    let (server, name) = IpcServer::<i32>::with_options(&options).unwrap();
+    assert!(name.starts_with('\0'));
+    for i in 0..2 {
+        let tx = IpcSender::connect(name.clone()).unwrap();
+        tx.send(i).unwrap();
+        assert_eq!(server.accept().unwrap().recv().unwrap(), i);
+    }
+
+    let options = IpcServerOptions {
+        abstract_namespace: true,
+        mode: Some(0o600),
+        ..Default::default()
+    };
+    assert!(IpcServer::<()>::with_options(&options).is_err());
+}
+
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn spawn_with_bootstrap_channels() {
+    let mut command = Command::new(env::current_exe().unwrap());
+    command.args(&["--exact", "test::spawned_child"]).stdout(Stdio::null());
+    let (mut child, tx, rx) = ipc::spawn::<u32,u32>(&mut command).unwrap();
+    tx.send(21).unwrap();
+    assert_eq!(rx.recv().unwrap(), 42);
+
+    // Spawning the same command again mustn't hand the first child's channels
+    // to the second one.
+    let (mut other_child, other_tx, other_rx) = ipc::spawn::<u32,u32>(&mut command).unwrap();
+    other_tx.send(1).unwrap();
+    assert_eq!(other_rx.recv().unwrap(), 2);
+
+    // The child keeps going until we hang up.
+    drop(tx);
+    match rx.recv() {
+        Err(RecvError::Disconnected) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(child.wait().unwrap().success());
+
+    drop(other_tx);
+    assert!(other_rx.recv().is_err());
+    assert!(other_child.wait().unwrap().success());
+
+    // This process wasn't started with `spawn()`.
+    assert!(ipc::bootstrap::<u32,u32>().is_err());
+}
+
+/// Run by `spawn_with_bootstrap_channels()` in a child process; does nothing otherwise.
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn spawned_child() {
+    let (rx, tx) = match ipc::bootstrap::<u32,u32>() {
+        Ok(channels) => channels,
+        Err(_) => return,
+    };
+    while let Ok(data) = rx.recv() {
+        tx.send(data * 2).unwrap();
+    }
+}
+
+#[cfg(all(not(feature = "force-inprocess"), any(target_os = "linux", target_os = "freebsd")))]
+#[test]
+fn raw_fd_conversions() {
+    let (tx, rx) = ipc::channel::<u32>().unwrap();
+    let rx_fd = rx.as_raw_fd();
+    assert_eq!(rx.into_raw_fd(), rx_fd);
+    let rx = unsafe { IpcReceiver::<u32>::from_raw_fd(rx_fd) };
+
+    // A clone keeps the original file descriptor, so this gets a duplicate.
+    let tx_clone = tx.clone();
+    let tx_fd = tx.into_raw_fd();
+    assert!(tx_fd != tx_clone.as_raw_fd());
+    let tx = unsafe { IpcSender::<u32>::from_raw_fd(tx_fd) };
+    tx.send(1).unwrap();
+    tx_clone.send(2).unwrap();
+    assert_eq!(rx.recv().unwrap(), 1);
+    assert_eq!(rx.recv().unwrap(), 2);
+
+    let shared_memory = IpcSharedMemory::from_bytes(b"shared");
+    let shared_memory_fd = shared_memory.as_raw_fd();
+    assert_eq!(shared_memory.into_raw_fd(), shared_memory_fd);
+    let shared_memory = unsafe { IpcSharedMemory::from_raw_fd(shared_memory_fd) };
+    assert_eq!(&*shared_memory, b"shared");
+}
+
+#[cfg(not(any(feature = "force-inprocess", target_os = "windows", target_os = "android", target_os = "ios")))]
+#[test]
+fn server_peer_credentials() {
+    let (mut server, name) = IpcServer::<()>::new().unwrap();
+    server.set_peer_policy(PeerPolicy::SameUser);
+    let child_pid = unsafe { fork(|| {
+        let tx = IpcSender::connect(name).unwrap();
+        tx.send(()).unwrap();
+    })};
+    let rx = server.accept().unwrap();
+    rx.recv().unwrap();
+    let credentials = rx.peer_credentials().unwrap();
+    child_pid.wait();
+    assert!(credentials.pid.map_or(true, |pid| pid == child_pid as u32));
+    assert_eq!(credentials.uid, unsafe { libc::geteuid() });
+    assert_eq!(credentials.gid, unsafe { libc::getegid() });
+}
+
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+#[test]
+fn server_peer_policy() {
+    let (mut server, name) = IpcServer::<i32>::new().unwrap();
+    let seen = Arc::new(Mutex::new(0));
+    let seen_by_policy = seen.clone();
+    // Reject every other client.
+    server.set_peer_policy(PeerPolicy::Allow(Box::new(move |_| {
+        let mut seen = seen_by_policy.lock().unwrap();
+        *seen += 1;
+        *seen % 2 == 0
+    })));
+    let client = thread::spawn(move || {
+        let rejected_tx = IpcSender::connect(name.clone()).unwrap();
+        // The server may already have hung up on us.
+        drop(rejected_tx.send(1));
+        let tx = IpcSender::connect(name).unwrap();
+        tx.send(2).unwrap();
+    });
+    assert_eq!(server.accept().unwrap().recv().unwrap(), 2);
+    assert_eq!(*seen.lock().unwrap(), 2);
+    client.join().unwrap();
+}
+
+#[cfg(any(feature = "force-inprocess", not(target_os = "macos")))]
+#[test]
+fn one_shot_server_peer_policy() {
+    let (mut server, name) = IpcOneShotServer::<i32>::new().unwrap();
+    let seen = Arc::new(Mutex::new(0));
+    let seen_by_policy = seen.clone();
+    // Reject the first client.
+    server.set_peer_policy(PeerPolicy::Allow(Box::new(move |_| {
+        let mut seen = seen_by_policy.lock().unwrap();
+        *seen += 1;
+        *seen > 1
+    })));
+    let client = thread::spawn(move || {
+        let rejected_tx = IpcSender::connect(name.clone()).unwrap();
+        drop(rejected_tx.send(1));
+        let tx = IpcSender::connect(name).unwrap();
+        tx.send(2).unwrap();
+    });
+    let (_, received) = server.accept().unwrap();
+    assert_eq!(received, 2);
+    assert_eq!(*seen.lock().unwrap(), 2);
+    client.join().unwrap();
+}
+
+#[test]
+fn iter() {
+    let (tx, rx) = ipc::channel().unwrap();
+    let thread = thread::spawn(move || {
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+    });
+    let received: Vec<u32> = rx.iter().map(|result| result.unwrap()).collect();
+    assert_eq!(received, vec![0, 1, 2, 3, 4]);
+    thread.join().unwrap();
+}
+
+#[test]
+fn try_iter() {
+    let (tx, rx) = ipc::channel().unwrap();
+    assert_eq!(rx.try_iter().count(), 0);
+    tx.send(1).unwrap();
+    tx.send(2).unwrap();
+    let received: Vec<u32> = rx.try_iter().map(|result| result.unwrap()).collect();
+    assert_eq!(received, vec![1, 2]);
+    tx.send(3).unwrap();
+    drop(tx);
+    let received: Vec<u32> = rx.try_iter().map(|result| result.unwrap()).collect();
+    assert_eq!(received, vec![3]);
+}
+
+#[test]
+fn into_iter_surfaces_errors() {
+    let (tx, rx) = ipc::channel::<String>().unwrap();
+    let opaque_tx = tx.clone().to_opaque().to::<Vec<u32>>();
+    tx.send("before".to_owned()).unwrap();
+    opaque_tx.send(vec![0xffffffff]).unwrap();
+    tx.send("after".to_owned()).unwrap();
+    drop((tx, opaque_tx));
+    let mut iter = rx.into_iter();
+    assert_eq!(iter.next().unwrap().unwrap(), "before");
+    match iter.next() {
+        Some(Err(RecvError::Deserialize(_))) => {}
+        result => panic!("expected deserialization error, got {:?}", result),
+    }
+    assert_eq!(iter.next().unwrap().unwrap(), "after");
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn bytes_iter() {
+    let (tx, rx) = ipc::bytes_channel().unwrap();
+    tx.send(&[1, 2]).unwrap();
+    tx.send(&[3]).unwrap();
+    assert_eq!(rx.try_iter().map(|result| result.unwrap()).collect::<Vec<_>>(),
+               vec![vec![1, 2], vec![3]]);
+    tx.send(&[4]).unwrap();
+    drop(tx);
+    let mut received = vec![];
+    for bytes in &rx {
+        received.push(bytes.unwrap());
+    }
+    assert_eq!(received, vec![vec![4]]);
+}
+
 #[test]
 fn multiple_paths_to_a_sender() {
     let person = ("Patrick Walton".to_owned(), 29);